@@ -0,0 +1,144 @@
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fs, io, iter};
+
+use crate::config;
+use crate::module::{self, Block, ClickEvent, Module};
+use crate::uevent;
+
+fn read_u32(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub(crate) struct Backlight {
+    /// The device directory in `/sys/class/backlight`.
+    path: PathBuf,
+    brightness: u32,
+    max_brightness: u32,
+    /// How often to read the brightness again. Writes to sysfs generate uevents, but some drivers
+    /// don't notify the changes made by the firmware, such as brightness keys.
+    resync_interval: Option<Duration>,
+    timeout: Option<Instant>,
+}
+
+impl Backlight {
+    /// The change in brightness when scrolling, in percent.
+    const STEP: u32 = 5;
+
+    /// Uses the first backlight device by name.
+    pub(crate) fn new(config: &config::Section) -> io::Result<Self> {
+        let no_device = || module::no_device("no backlight device found");
+        let mut paths = match fs::read_dir("/sys/class/backlight") {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(no_device()),
+            Err(err) => return Err(err),
+        };
+        paths.sort();
+        let path = paths.into_iter().next().ok_or_else(no_device)?;
+        let max_brightness = read_u32(&path.join("max_brightness"))?;
+        let brightness = read_u32(&path.join("brightness"))?;
+        let resync_interval = config.duration_or("resync_interval", None);
+        Ok(Self {
+            path,
+            brightness,
+            max_brightness,
+            resync_interval,
+            timeout: resync_interval.map(|i| Instant::now() + i),
+        })
+    }
+
+    fn percentage(&self) -> u32 {
+        let max = self.max_brightness.max(1);
+        (self.brightness * 100 + max / 2) / max
+    }
+
+    fn set_percentage(&self, percentage: u32) -> io::Result<()> {
+        // Don't turn the screen off completely.
+        let percentage = percentage.clamp(1, 100);
+        let value = (percentage * self.max_brightness + 50) / 100;
+        fs::write(self.path.join("brightness"), value.to_string())
+    }
+
+    /// Reads the brightness again. Returns `true` if it changed.
+    fn read_brightness(&mut self) -> bool {
+        match read_u32(&self.path.join("brightness")) {
+            Ok(brightness) => {
+                let dirty = brightness != self.brightness;
+                self.brightness = brightness;
+                dirty
+            }
+            Err(err) => {
+                eprintln!("failed to read brightness: {err}");
+                false
+            }
+        }
+    }
+}
+
+impl Module for Backlight {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let block = Block {
+            text: format!("Brightness: {}%", self.percentage()),
+            is_warning: false,
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        self.timeout = self.resync_interval.map(|i| Instant::now() + i);
+        self.read_brightness()
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        self.timeout
+    }
+
+    fn uevent_subscriptions(&self) -> Vec<uevent::Subscription> {
+        vec![uevent::Subscription::subsystem("backlight")]
+    }
+
+    fn uevent(&mut self, _event: &uevent::Event) -> bool {
+        self.read_brightness()
+    }
+
+    fn uevent_overflow(&mut self) -> bool {
+        self.read_brightness()
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        let percentage = self.percentage();
+        let ret = match event.button {
+            4 => self.set_percentage(percentage + Backlight::STEP),
+            5 => self.set_percentage(percentage.saturating_sub(Backlight::STEP)),
+            _ => return false,
+        };
+        if let Err(err) = ret {
+            eprintln!("failed to change brightness: {err}");
+        }
+        // The write generates a uevent, but the new brightness can be shown without waiting for it.
+        self.read_brightness()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        if key != "brightness" {
+            return Err(module::unknown_option(key));
+        }
+        let percentage = value
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| module::invalid_value(key, value))?;
+        self.set_percentage(percentage)?;
+        Ok(self.read_brightness())
+    }
+}
@@ -1,11 +1,13 @@
-use std::collections::HashMap;
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
 use std::{fs, io};
 
-use crate::module::{Block, Module};
+use crate::config;
+use crate::module::{self, Block, Module};
 use crate::uevent;
 
+#[derive(PartialEq)]
 struct Battery {
     energy_full: u64,
     energy_now: u64,
@@ -36,20 +38,111 @@ impl Battery {
     }
 }
 
+/// A battery inside a peripheral (e.g. a Bluetooth headset or a wireless mouse), as opposed to a
+/// battery that powers the system.
+#[derive(PartialEq)]
+struct Peripheral {
+    model_name: String,
+    capacity: Option<u8>,
+    capacity_level: Option<String>,
+}
+
+impl Peripheral {
+    fn is_peripheral(vars: &HashMap<String, String>) -> bool {
+        vars.get("POWER_SUPPLY_SCOPE").map(String::as_str) == Some("Device")
+    }
+
+    fn from_vars(vars: &HashMap<String, String>) -> Result<Self, MissingOrInvalidProperty> {
+        let model_name = vars
+            .get("POWER_SUPPLY_MODEL_NAME")
+            .or_else(|| vars.get("POWER_SUPPLY_NAME"))
+            .ok_or(MissingOrInvalidProperty)?;
+        let capacity = vars
+            .get("POWER_SUPPLY_CAPACITY")
+            .and_then(|s| s.parse().ok());
+        // Some devices only report a coarse level such as "Low" or "Full".
+        let capacity_level = vars.get("POWER_SUPPLY_CAPACITY_LEVEL").cloned();
+        if capacity.is_none() && capacity_level.is_none() {
+            return Err(MissingOrInvalidProperty);
+        }
+        Ok(Self {
+            model_name: model_name.to_string(),
+            capacity,
+            capacity_level,
+        })
+    }
+
+    fn render(&self) -> Block {
+        match self.capacity {
+            Some(capacity) => Block {
+                text: format!("{}: {}%", self.model_name, capacity),
+                is_warning: capacity <= 15,
+                ..Default::default()
+            },
+            None => {
+                let level = self.capacity_level.as_deref().unwrap_or_default();
+                Block {
+                    text: format!("{}: {}", self.model_name, level),
+                    is_warning: level == "Low" || level == "Critical",
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
 pub(crate) struct Batteries {
-    uevent_socket: uevent::Socket,
-    map: HashMap<String, Battery>,
+    // The maps are keyed by devpath, and ordered so that the blocks keep their order, and their
+    // instances with it.
+    map: BTreeMap<String, Battery>,
+    peripherals: BTreeMap<String, Peripheral>,
+    /// How often to scan the power supplies again, in case events were missed.
+    resync_interval: Option<Duration>,
+    timeout: Option<Instant>,
 }
 
 impl Batteries {
-    pub(crate) fn new() -> io::Result<Batteries> {
-        let map = Batteries::scan_batteries()?;
-        let uevent_socket = uevent::Socket::open_and_bind(true, true)?;
-        Ok(Batteries { uevent_socket, map })
+    const DEFAULT_RESYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    pub(crate) fn new(config: &config::Section) -> io::Result<Batteries> {
+        let (map, peripherals) = Batteries::scan_batteries().map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                module::no_device("no power supply class found")
+            } else {
+                err
+            }
+        })?;
+        let resync_interval =
+            config.duration_or("resync_interval", Some(Batteries::DEFAULT_RESYNC_INTERVAL));
+        Ok(Batteries {
+            map,
+            peripherals,
+            resync_interval,
+            timeout: resync_interval.map(|i| Instant::now() + i),
+        })
+    }
+
+    /// Scans the power supplies again and replaces the ones that are known. Returns `true` if
+    /// anything changed.
+    fn rescan(&mut self) -> bool {
+        self.timeout = self.resync_interval.map(|i| Instant::now() + i);
+        match Batteries::scan_batteries() {
+            Ok((map, peripherals)) => {
+                let dirty = map != self.map || peripherals != self.peripherals;
+                self.map = map;
+                self.peripherals = peripherals;
+                dirty
+            }
+            Err(err) => {
+                eprintln!("failed to scan power supplies: {err}");
+                false
+            }
+        }
     }
 
-    fn scan_batteries() -> io::Result<HashMap<String, Battery>> {
-        let mut map = HashMap::new();
+    fn scan_batteries() -> io::Result<(BTreeMap<String, Battery>, BTreeMap<String, Peripheral>)> {
+        let mut map = BTreeMap::new();
+        let mut peripherals = BTreeMap::new();
         for entry in fs::read_dir("/sys/class/power_supply")? {
             let entry = entry?;
             let type_ = match entry.file_type() {
@@ -94,63 +187,76 @@ impl Batteries {
             };
             // This will fail for power supply devices that aren't batteries, so no error should be
             // logged.
-            if let Ok(info) = Battery::from_vars(&vars) {
+            if Peripheral::is_peripheral(&vars) {
+                if let Ok(info) = Peripheral::from_vars(&vars) {
+                    peripherals.insert(devpath.to_owned(), info);
+                }
+            } else if let Ok(info) = Battery::from_vars(&vars) {
                 map.insert(devpath.to_owned(), info);
             }
         }
-        Ok(map)
+        Ok((map, peripherals))
     }
 }
 
 impl Module for Batteries {
     fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
-        Box::new(self.map.values().map(|bat| {
+        let batteries = self.map.values().map(|bat| {
             let percentage = bat.energy_now * 100 / bat.energy_full;
             Block {
                 text: format!("Battery: {}% ({})", percentage, bat.status),
                 is_warning: percentage <= 15,
+                ..Default::default()
             }
-        }))
+        });
+        Box::new(batteries.chain(self.peripherals.values().map(Peripheral::render)))
     }
 
     fn update(&mut self) -> bool {
+        self.rescan()
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        self.timeout
+    }
+
+    fn uevent_subscriptions(&self) -> Vec<uevent::Subscription> {
+        vec![uevent::Subscription::subsystem("power_supply")]
+    }
+
+    fn uevent(&mut self, event: &uevent::Event) -> bool {
+        let action = match event.vars.get("ACTION") {
+            Some(a) => a,
+            None => return false,
+        };
+        let devpath = match event.vars.get("DEVPATH") {
+            Some(d) => d,
+            None => return false,
+        };
         let mut dirty = false;
-        loop {
-            let event = match self.uevent_socket.recv_event() {
-                Ok(e) => e,
-                Err(err) => {
-                    if err.kind() != io::ErrorKind::WouldBlock {
-                        eprintln!("failed to read uevent: {err}");
-                    }
-                    break;
-                }
-            };
-            let action = match event.vars.get("ACTION") {
-                Some(a) => a,
-                None => continue,
-            };
-            let devpath = match event.vars.get("DEVPATH") {
-                Some(d) => d,
-                None => continue,
-            };
-            if action == "add" || action == "change" {
-                // This will fail for devices that aren't batteries, so no error should be logged.
-                if let Ok(info) = Battery::from_vars(&event.vars) {
-                    self.map.insert(devpath.to_owned(), info);
+        if action == "add" || action == "change" {
+            // This will fail for devices that aren't batteries, so no error should be logged.
+            if Peripheral::is_peripheral(&event.vars) {
+                if let Ok(info) = Peripheral::from_vars(&event.vars) {
+                    self.peripherals.insert(devpath.to_owned(), info);
                     dirty = true;
                 }
-            } else if action == "remove" {
-                dirty |= self.map.remove(devpath).is_some();
+            } else if let Ok(info) = Battery::from_vars(&event.vars) {
+                self.map.insert(devpath.to_owned(), info);
+                dirty = true;
             }
+        } else if action == "remove" {
+            dirty |= self.map.remove(devpath).is_some();
+            dirty |= self.peripherals.remove(devpath).is_some();
         }
         dirty
     }
 
-    fn pollable_fd(&self) -> Option<RawFd> {
-        Some(self.uevent_socket.as_raw_fd())
-    }
-
-    fn timeout(&self) -> Option<Instant> {
-        None
+    fn uevent_overflow(&mut self) -> bool {
+        self.rescan()
     }
 }
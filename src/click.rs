@@ -0,0 +1,87 @@
+use std::convert::TryFrom;
+use std::io;
+
+use crate::json;
+
+/// A click event sent by i3bar on standard input.
+pub(crate) struct Click {
+    pub name: String,
+    pub instance: String,
+    pub button: u32,
+}
+
+impl Click {
+    fn parse(line: &str) -> Result<Self, json::ParseError> {
+        let value = json::parse(line)?;
+        let field = |key| value.get(key).and_then(json::Value::as_str).unwrap_or("");
+        Ok(Self {
+            name: field("name").to_owned(),
+            instance: field("instance").to_owned(),
+            button: value
+                .get("button")
+                .and_then(json::Value::as_u32)
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// Reads click events from standard input.
+pub(crate) struct Reader {
+    buf: Vec<u8>,
+    is_eof: bool,
+}
+
+impl Reader {
+    pub(crate) const FD: libc::c_int = libc::STDIN_FILENO;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    /// Returns `true` once standard input was closed, in which case it shouldn't be polled
+    /// anymore.
+    pub(crate) fn is_eof(&self) -> bool {
+        self.is_eof
+    }
+
+    /// Reads from standard input once, so it won't block if it was polled before, and returns the
+    /// click events from the complete lines.
+    pub(crate) fn read(&mut self) -> io::Result<Vec<Click>> {
+        let mut chunk = [0u8; 4096];
+        let ret = unsafe {
+            libc::read(
+                Reader::FD,
+                chunk.as_mut_ptr() as *mut libc::c_void,
+                chunk.len(),
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let read = usize::try_from(ret).unwrap();
+        if read == 0 {
+            self.is_eof = true;
+        }
+        self.buf.extend_from_slice(&chunk[..read]);
+
+        let mut clicks = Vec::new();
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            // The events are the elements of an infinite JSON array: the first line opens it and
+            // the others start with a comma.
+            let line = line.trim().trim_start_matches(['[', ',']);
+            if line.is_empty() {
+                continue;
+            }
+            match Click::parse(line) {
+                Ok(c) => clicks.push(c),
+                Err(err) => eprintln!("failed to parse click event: {:?}", err),
+            }
+        }
+        Ok(clicks)
+    }
+}
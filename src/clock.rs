@@ -1,76 +1,322 @@
 use std::{
-    iter,
-    os::unix::io::RawFd,
+    convert::TryFrom,
+    io, iter,
+    os::unix::io::{AsRawFd, RawFd},
     time::{Duration, Instant},
 };
 
-use time::OffsetDateTime;
+use time::format_description::{self, Component, FormatItem};
+use time::{OffsetDateTime, UtcOffset};
 
-use crate::module::{Block, Module};
+use crate::command::spawn_detached;
+use crate::config;
+use crate::module::{Block, ClickEvent, Module};
+use crate::timechange::WallTimer;
+use crate::zoneinfo::TimeZone;
 
-pub(crate) struct Clock {
-    timeout: Instant,
-    hour: u8,
-    minute: u8,
+/// The smallest unit of time that a format displays.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Unit {
+    Second,
+    Minute,
+    /// Also used for dates, because the offset from UTC usually changes on the hour.
+    Hour,
 }
 
-impl Clock {
-    pub(crate) fn new() -> Self {
-        let now = Clock::read();
-        Self {
-            timeout: Instant::now() + Clock::time_until_next_minute(now),
-            hour: now.hour(),
-            minute: now.minute(),
+impl Unit {
+    fn secs(self) -> u64 {
+        match self {
+            Unit::Second => 1,
+            Unit::Minute => 60,
+            Unit::Hour => 60 * 60,
         }
     }
+}
 
-    fn read() -> OffsetDateTime {
-        OffsetDateTime::now_local().unwrap_or_else(|err| {
-            eprintln!("failed to retreive local timezone: {:?}", err);
-            OffsetDateTime::now_utc()
+/// Returns the smallest unit of time that a format description displays.
+fn smallest_unit(items: &[FormatItem<'_>]) -> Unit {
+    items
+        .iter()
+        .map(|item| match item {
+            FormatItem::Component(Component::Second(_) | Component::Subsecond(_)) => Unit::Second,
+            FormatItem::Component(Component::Minute(_)) => Unit::Minute,
+            FormatItem::Compound(items) | FormatItem::First(items) => smallest_unit(items),
+            FormatItem::Optional(item) => smallest_unit(std::slice::from_ref(*item)),
+            _ => Unit::Hour,
         })
+        .min()
+        .unwrap_or(Unit::Hour)
+}
+
+/// Returns an error if an option is not a valid format description.
+fn check_format(key: &str, format: &str) -> io::Result<()> {
+    match format_description::parse(format) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid {key} option: {err}"),
+        )),
     }
+}
 
-    fn time_until_next_minute(time: OffsetDateTime) -> Duration {
-        const MARGIN_SECS: u8 = 1;
-        Duration::new(
-            // Round up to the next minute, adding a small margin to account for
-            // error.
-            (59 - (time.second() % 60) + MARGIN_SECS).into(),
-            1_000_000_000 - (time.nanosecond() % 1_000_000_000),
-        )
+/// Shows the time in the local time zone, or in a time zone of the database. A click shows the
+/// date instead, until the next click.
+pub(crate) struct Clock {
+    /// A format description such as `[hour]:[minute]`. See the book of the `time` crate.
+    format: String,
+    /// The format description of the date.
+    date_format: String,
+    is_date_shown: bool,
+    /// A shell command that runs when the block is clicked with `command_button`.
+    command: Option<String>,
+    command_button: u32,
+    time_zone: Option<TimeZone>,
+    /// Expires when the shown time changes. It follows the system clock, so the time is right
+    /// as soon as the system resumes or the clock is set. It keeps firing while the bar is
+    /// hidden, which only costs one update per displayed unit.
+    timer: WallTimer,
+    text: String,
+}
+
+impl Clock {
+    const DEFAULT_FORMAT: &'static str = "[hour]:[minute]";
+    const DEFAULT_DATE_FORMAT: &'static str =
+        "[weekday repr:long] [day padding:none] [month repr:long] [year]";
+    /// The left button.
+    const DATE_BUTTON: u32 = 1;
+    /// The right button.
+    const DEFAULT_COMMAND_BUTTON: u32 = 3;
+
+    /// Creates the module of the `[clock]` section, or of a `[clock:<name>]` section. The latter
+    /// shows its name before the time by default, and its time zone defaults to its name.
+    pub(crate) fn new(name: Option<&str>, config: &config::Section) -> io::Result<Self> {
+        let default_format = |format: &str| match name {
+            // Brackets start components, so they must be doubled.
+            Some(name) => format!("{} {}", name.replace('[', "[["), format),
+            None => format.to_owned(),
+        };
+        let format = match config.get("format") {
+            Some(f) => f.to_owned(),
+            None => default_format(Clock::DEFAULT_FORMAT),
+        };
+        check_format("format", &format)?;
+        let date_format = match config.get("date_format") {
+            Some(f) => f.to_owned(),
+            None => default_format(Clock::DEFAULT_DATE_FORMAT),
+        };
+        check_format("date_format", &date_format)?;
+        let command_button = match config.get("command_button") {
+            Some(b) => b.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid command_button option")
+            })?,
+            None => Clock::DEFAULT_COMMAND_BUTTON,
+        };
+        let time_zone = match config.get("timezone").or(name) {
+            // A missing file means that the option or the name isn't a time zone.
+            Some(tz) => Some(TimeZone::load(tz).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("failed to load time zone {tz}: {err}"),
+                )
+            })?),
+            None => None,
+        };
+        let mut r = Self {
+            format,
+            date_format,
+            is_date_shown: false,
+            command: config.get("command").map(str::to_owned),
+            command_button,
+            time_zone,
+            timer: WallTimer::open()?,
+            text: String::new(),
+        };
+        r.update();
+        Ok(r)
+    }
+
+    fn read(&self) -> OffsetDateTime {
+        let now = OffsetDateTime::now_utc();
+        let time_zone = match &self.time_zone {
+            Some(tz) => tz,
+            None => {
+                return OffsetDateTime::now_local().unwrap_or_else(|err| {
+                    eprintln!("failed to retreive local timezone: {:?}", err);
+                    now
+                })
+            }
+        };
+        match UtcOffset::from_whole_seconds(time_zone.offset_at(now.unix_timestamp())) {
+            Ok(offset) => now.to_offset(offset),
+            Err(_) => now,
+        }
+    }
+
+    /// Returns the time until the unit of time changes.
+    fn time_until_next(time: OffsetDateTime, unit: Unit) -> Duration {
+        let secs = u64::from(time.minute()) * 60 + u64::from(time.second());
+        let elapsed = Duration::new(secs % unit.secs(), time.nanosecond());
+        Duration::from_secs(unit.secs()) - elapsed
     }
 }
 
 impl Module for Clock {
     fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
         let block = Block {
-            text: format!("{:02}:{:02}", self.hour, self.minute),
+            text: self.text.clone(),
             is_warning: false,
+            ..Default::default()
         };
         Box::new(iter::once(block))
     }
 
     fn update(&mut self) -> bool {
-        let now = Clock::read();
-        let mut dirty = false;
-        if self.hour != now.hour() {
-            self.hour = now.hour();
-            dirty = true;
+        // The timer is armed again below whether it expired or not.
+        if let Err(err) = self.timer.read() {
+            eprintln!("failed to read the timerfd of the clock: {err}");
         }
-        if self.minute != now.minute() {
-            self.minute = now.minute();
-            dirty = true;
+        let now = self.read();
+        let format = if self.is_date_shown {
+            &self.date_format
+        } else {
+            &self.format
+        };
+        // The formats were checked when the module was created.
+        let items = format_description::parse(format).unwrap();
+        let text = match now.format(&items) {
+            Ok(t) => t,
+            Err(err) => {
+                eprintln!("failed to format the time: {err}");
+                String::new()
+            }
+        };
+        let dirty = text != self.text;
+        self.text = text;
+        let since_epoch = u64::try_from(now.unix_timestamp_nanos())
+            .map(Duration::from_nanos)
+            .unwrap_or_default();
+        let at = since_epoch + Clock::time_until_next(now, smallest_unit(&items));
+        if let Err(err) = self.timer.set(at) {
+            eprintln!("failed to arm the timerfd of the clock: {err}");
         }
-        self.timeout = Instant::now() + Clock::time_until_next_minute(now);
         dirty
     }
 
     fn pollable_fd(&self) -> Option<RawFd> {
-        None
+        Some(self.timer.as_raw_fd())
     }
 
     fn timeout(&self) -> Option<Instant> {
-        Some(self.timeout)
+        None
+    }
+
+    fn time_changed(&mut self) -> bool {
+        self.update()
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        if let Some(command) = &self.command {
+            if event.button == self.command_button {
+                if let Err(err) = spawn_detached(command) {
+                    eprintln!("failed to run the command of the clock: {err}");
+                }
+                return false;
+            }
+        }
+        if event.button != Clock::DATE_BUTTON {
+            return false;
+        }
+        self.is_date_shown = !self.is_date_shown;
+        self.update()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::format_description::modifier;
+    use time::{Date, Month, PrimitiveDateTime, Time};
+
+    fn unit(format: &str) -> Unit {
+        smallest_unit(&format_description::parse(format).unwrap())
+    }
+
+    #[test]
+    fn units() {
+        assert!(unit("[hour]:[minute]:[second]") == Unit::Second);
+        assert!(unit("[second].[subsecond]") == Unit::Second);
+        assert!(unit("[hour]:[minute]") == Unit::Minute);
+        assert!(unit("[weekday] [day] [month repr:short] [year]") == Unit::Hour);
+        assert!(unit("no components") == Unit::Hour);
+    }
+
+    #[test]
+    fn units_of_optional_items() {
+        // The parser of this version of the time crate doesn't know `[optional [...]]`.
+        let colon = || FormatItem::Literal(b":");
+        let hour = || FormatItem::Component(Component::Hour(modifier::Hour::default()));
+        let minute = || FormatItem::Component(Component::Minute(modifier::Minute::default()));
+        let second = FormatItem::Component(Component::Second(modifier::Second::default()));
+        // [hour][optional [:[minute]]]
+        let minutes = [colon(), minute()];
+        let items = [
+            hour(),
+            FormatItem::Optional(&FormatItem::Compound(&minutes)),
+        ];
+        assert!(smallest_unit(&items) == Unit::Minute);
+        // [hour][optional [:[minute][optional [:[second]]]]]
+        let seconds = [colon(), second];
+        let optional_seconds = FormatItem::Optional(&FormatItem::Compound(&seconds));
+        let minutes = [colon(), minute(), optional_seconds];
+        let items = [
+            hour(),
+            FormatItem::Optional(&FormatItem::Compound(&minutes)),
+        ];
+        assert!(smallest_unit(&items) == Unit::Second);
+    }
+
+    fn time_until_next(offset: UtcOffset, unit: Unit) -> Duration {
+        let date = Date::from_calendar_date(2024, Month::March, 10).unwrap();
+        let time = Time::from_hms_milli(10, 15, 30, 250).unwrap();
+        Clock::time_until_next(
+            PrimitiveDateTime::new(date, time).assume_offset(offset),
+            unit,
+        )
+    }
+
+    #[test]
+    fn next_change() {
+        let utc = UtcOffset::UTC;
+        assert_eq!(
+            time_until_next(utc, Unit::Second),
+            Duration::from_millis(750)
+        );
+        assert_eq!(
+            time_until_next(utc, Unit::Minute),
+            Duration::from_millis(29_750)
+        );
+        assert_eq!(
+            time_until_next(utc, Unit::Hour),
+            Duration::from_millis(44 * 60_000 + 29_750)
+        );
+        // The hour changes at half past in UTC.
+        let india = UtcOffset::from_hms(5, 30, 0).unwrap();
+        assert_eq!(
+            time_until_next(india, Unit::Minute),
+            Duration::from_millis(29_750)
+        );
+        assert_eq!(
+            time_until_next(india, Unit::Hour),
+            Duration::from_millis(44 * 60_000 + 29_750)
+        );
+    }
+
+    #[test]
+    fn invalid_time_zone() {
+        let err = Clock::new(Some("Nowhere/Zone"), &config::Section::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 }
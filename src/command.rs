@@ -0,0 +1,348 @@
+use std::io::{self, Read};
+use std::iter;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Child, ChildStdout, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::module::{Block, ClickEvent, Module};
+use crate::signal;
+
+pub(crate) fn set_non_blocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let ret = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Runs a shell command in the background without waiting for it to finish. Its output is
+/// discarded so that it doesn't mix with the status lines.
+pub(crate) fn spawn_detached(command: &str) -> io::Result<()> {
+    // The first shell starts the command in the background and exits right away, so the command
+    // is adopted by init and doesn't become a zombie.
+    let mut shell = process::Command::new("/bin/sh");
+    shell
+        .arg("-c")
+        .arg("/bin/sh -c \"$1\" &")
+        .arg("sh")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+    unsafe { shell.pre_exec(signal::unblock_all) };
+    shell.spawn()?.wait()?;
+    Ok(())
+}
+
+/// When the command runs.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// Once at startup, and again when the block is clicked or refreshed.
+    Once,
+    /// Again after each interval, counted from the end of the previous run.
+    Interval(Duration),
+    /// The command keeps running and each line it prints replaces the text of the block.
+    Persist,
+}
+
+impl Mode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "once" => Some(Mode::Once),
+            "persist" => Some(Mode::Persist),
+            _ => match config::parse_duration(s)? {
+                d if d.is_zero() => Some(Mode::Once),
+                d => Some(Mode::Interval(d)),
+            },
+        }
+    }
+}
+
+#[derive(Default, PartialEq)]
+struct Output {
+    full_text: String,
+    short_text: Option<String>,
+    color: Option<String>,
+}
+
+/// Parses the output of a command as i3blocks does: the first line is the full text, the second
+/// one the short text and the third one the color. The other lines are ignored.
+fn parse_output(output: &str) -> Output {
+    let mut lines = output.lines();
+    let mut next = || {
+        lines
+            .next()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty())
+            .map(str::to_owned)
+    };
+    Output {
+        full_text: next().unwrap_or_default(),
+        short_text: next(),
+        color: next(),
+    }
+}
+
+struct Running {
+    child: Child,
+    /// `None` once the command closed its output.
+    stdout: Option<ChildStdout>,
+    buf: Vec<u8>,
+}
+
+/// Runs a shell command and shows its output, in a way that is compatible with i3blocks.
+pub(crate) struct Command {
+    /// Passed to the command as `BLOCK_NAME`.
+    name: String,
+    /// Passed to the command as `BLOCK_INSTANCE`.
+    instance: Option<String>,
+    command: String,
+    mode: Mode,
+    running: Option<Running>,
+    output: Output,
+    is_urgent: bool,
+    /// Whether the command exited with an error.
+    is_failed: bool,
+    /// The button of a click that happened while the command was running. It runs again once
+    /// it exits.
+    pending_button: Option<u32>,
+    timeout: Option<Instant>,
+}
+
+impl Command {
+    /// The exit code that makes the block urgent.
+    const URGENT_EXIT_CODE: i32 = 33;
+
+    /// How often to check whether a command that closed its output exited.
+    const REAP_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Creates the module of a `[command:<name>]` section and runs the command.
+    pub(crate) fn new(name: &str, config: &config::Section) -> io::Result<Self> {
+        let command = config
+            .get("command")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing command option"))?;
+        let mode = match config.get("interval") {
+            Some(v) => Mode::parse(v).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid interval option")
+            })?,
+            None => Mode::Once,
+        };
+        let mut r = Self {
+            name: name.to_owned(),
+            instance: config.get("instance").map(str::to_owned),
+            command: command.to_owned(),
+            mode,
+            running: None,
+            output: Output::default(),
+            is_urgent: false,
+            is_failed: false,
+            pending_button: None,
+            timeout: None,
+        };
+        r.spawn(None)?;
+        Ok(r)
+    }
+
+    /// Runs the command, with the button that was clicked if any.
+    fn spawn(&mut self, button: Option<u32>) -> io::Result<()> {
+        let mut command = process::Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .env("BLOCK_NAME", &self.name);
+        if let Some(instance) = &self.instance {
+            command.env("BLOCK_INSTANCE", instance);
+        }
+        if let Some(button) = button {
+            command.env("BLOCK_BUTTON", button.to_string());
+        }
+        unsafe { command.pre_exec(signal::unblock_all) };
+        let mut child = command.spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        set_non_blocking(stdout.as_raw_fd())?;
+        self.running = Some(Running {
+            child,
+            stdout: Some(stdout),
+            buf: Vec::new(),
+        });
+        self.timeout = None;
+        Ok(())
+    }
+
+    /// Runs the command, or schedules the next try if it can't run.
+    fn run(&mut self, button: Option<u32>) {
+        if let Err(err) = self.spawn(button) {
+            eprintln!("failed to run the command of {}: {err}", self.name);
+            self.timeout = match self.mode {
+                Mode::Interval(interval) => Some(Instant::now() + interval),
+                _ => None,
+            };
+        }
+    }
+
+    /// Reads what the running command printed, and handles its exit once its output is closed.
+    /// Returns `true` if the blocks need to be rerendered.
+    fn read(&mut self) -> bool {
+        let running = match &mut self.running {
+            Some(r) => r,
+            None => return false,
+        };
+        let stdout = match &mut running.stdout {
+            Some(s) => s,
+            None => return self.reap(),
+        };
+        let mut chunk = [0u8; 4096];
+        let is_eof = loop {
+            match stdout.read(&mut chunk) {
+                Ok(0) => break true,
+                Ok(read) => running.buf.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break false,
+                Err(err) => {
+                    eprintln!("failed to read the output of {}: {err}", self.name);
+                    break true;
+                }
+            }
+        };
+
+        let mut dirty = false;
+        if self.mode == Mode::Persist {
+            let mut last_line = None;
+            while let Some(end) = running.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = running.buf.drain(..=end).collect();
+                last_line = Some(String::from_utf8_lossy(&line[..end]).trim_end().to_owned());
+            }
+            if let Some(line) = last_line {
+                let output = Output {
+                    full_text: line,
+                    ..Output::default()
+                };
+                dirty |= output != self.output;
+                self.output = output;
+            }
+        }
+        if !is_eof {
+            return dirty;
+        }
+
+        // The command closed its output, so it is exiting.
+        running.stdout = None;
+        dirty | self.reap()
+    }
+
+    /// Handles the exit of a command that closed its output, or checks again later if it is
+    /// still running. Returns `true` if the blocks need to be rerendered.
+    fn reap(&mut self) -> bool {
+        let running = self.running.as_mut().unwrap();
+        let code = match running.child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() && status.code() != Some(Command::URGENT_EXIT_CODE) {
+                    eprintln!("the command of {} failed: {status}", self.name);
+                }
+                status.code()
+            }
+            // Waiting would block the bar until the command exits.
+            Ok(None) => {
+                self.timeout = Some(Instant::now() + Command::REAP_INTERVAL);
+                return false;
+            }
+            Err(err) => {
+                eprintln!("failed to wait for the command of {}: {err}", self.name);
+                None
+            }
+        };
+        let running = self.running.take().unwrap();
+        self.timeout = None;
+        let mut dirty = false;
+        let is_urgent = code == Some(Command::URGENT_EXIT_CODE);
+        let is_failed = !is_urgent && code != Some(0);
+        if self.mode != Mode::Persist {
+            let output = parse_output(&String::from_utf8_lossy(&running.buf));
+            dirty |= output != self.output;
+            self.output = output;
+        }
+        dirty |= is_urgent != self.is_urgent || is_failed != self.is_failed;
+        self.is_urgent = is_urgent;
+        self.is_failed = is_failed;
+
+        if let Some(button) = self.pending_button.take() {
+            self.run(Some(button));
+        } else if let Mode::Interval(interval) = self.mode {
+            self.timeout = Some(Instant::now() + interval);
+        }
+        dirty
+    }
+}
+
+impl Module for Command {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        // i3blocks hides blocks without text, but a failure must be visible.
+        let text = if !self.output.full_text.is_empty() {
+            self.output.full_text.clone()
+        } else if self.is_failed {
+            format!("{}: failed", self.name)
+        } else {
+            return Box::new(iter::empty());
+        };
+        let block = Block {
+            text,
+            short_text: self.output.short_text.clone(),
+            color: self.output.color.clone(),
+            is_warning: self.is_failed,
+            is_urgent: self.is_urgent,
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        if self.running.is_some() {
+            return self.read();
+        }
+        // The interval elapsed, or the module is refreshed.
+        self.run(None);
+        false
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        self.running
+            .as_ref()
+            .and_then(|r| r.stdout.as_ref())
+            .map(AsRawFd::as_raw_fd)
+    }
+
+    fn hangup(&mut self) -> io::Result<bool> {
+        // The command exited, which is expected.
+        Ok(self.read())
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        self.timeout
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        if self.running.is_none() {
+            self.run(Some(event.button));
+        } else if self.mode != Mode::Persist {
+            self.pending_button = Some(event.button);
+        }
+        false
+    }
+}
+
+impl Drop for Command {
+    fn drop(&mut self) {
+        if let Some(running) = &mut self.running {
+            // Don't leave a zombie process behind.
+            if let Err(err) = running.child.kill().and_then(|_| running.child.wait()) {
+                eprintln!("failed to stop the command of {}: {err}", self.name);
+            }
+        }
+    }
+}
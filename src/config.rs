@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{fmt, fs, io};
+
+/// The options of one section of the configuration file.
+#[derive(Clone, Default)]
+pub(crate) struct Section {
+    pub name: String,
+    vars: HashMap<String, String>,
+}
+
+impl Section {
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    /// Returns the duration of an option, or `default` if it is missing or invalid. A zero
+    /// duration disables the feature, so `None` is returned.
+    pub(crate) fn duration_or(&self, key: &str, default: Option<Duration>) -> Option<Duration> {
+        let value = match self.get(key) {
+            Some(v) => v,
+            None => return default,
+        };
+        match parse_duration(value) {
+            Some(d) if d.is_zero() => None,
+            Some(d) => Some(d),
+            None => {
+                eprintln!("invalid duration for {} in [{}]: {}", key, self.name, value);
+                default
+            }
+        }
+    }
+}
+
+/// Parses a duration such as `300`, `500ms`, `30s`, `5m` or `1h`. A number without a unit is in
+/// seconds.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..split].parse().ok()?;
+    match s[split..].trim() {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(n.checked_mul(60 * 60)?)),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    /// A line is neither a section header, an option nor a comment. The line number starts at 1.
+    InvalidLine(usize),
+    /// An option appears before the first section header.
+    OptionOutsideSection(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidLine(line) => write!(f, "invalid line {}", line),
+            ParseError::OptionOutsideSection(line) => {
+                write!(f, "option outside of a section on line {}", line)
+            }
+        }
+    }
+}
+
+/// The configuration file. It is made of sections such as `[clock]` followed by `key = value`
+/// options. Lines starting with `#` are comments.
+#[derive(Default)]
+pub(crate) struct Config {
+    sections: Vec<Section>,
+    /// Returned for sections that are not in the file.
+    empty: Section,
+}
+
+impl Config {
+    /// Returns `$XDG_CONFIG_HOME/gstatus/config`, or `~/.config/gstatus/config`.
+    pub(crate) fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(d) if !d.is_empty() => PathBuf::from(d),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("gstatus").join("config"))
+    }
+
+    /// Loads the configuration file. The default configuration is returned if the file doesn't
+    /// exist or is invalid.
+    pub(crate) fn load() -> Self {
+        let path = match Config::path() {
+            Some(p) => p,
+            None => return Config::default(),
+        };
+        let s = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    eprintln!("failed to read {}: {}", path.display(), err);
+                }
+                return Config::default();
+            }
+        };
+        match Config::parse(&s) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("failed to parse {}: {}", path.display(), err);
+                Config::default()
+            }
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut sections: Vec<Section> = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push(Section {
+                    name: name.trim().to_owned(),
+                    vars: HashMap::new(),
+                });
+                continue;
+            }
+            let (k, v) = line.split_once('=').ok_or(ParseError::InvalidLine(i + 1))?;
+            let section = sections
+                .last_mut()
+                .ok_or(ParseError::OptionOutsideSection(i + 1))?;
+            section
+                .vars
+                .insert(k.trim().to_owned(), v.trim().to_owned());
+        }
+        Ok(Self {
+            sections,
+            empty: Section::default(),
+        })
+    }
+
+    /// Returns the sections in the order of the file.
+    pub(crate) fn sections(&self) -> impl Iterator<Item = &Section> {
+        self.sections.iter()
+    }
+
+    /// Returns the first section with the given name, or an empty one.
+    pub(crate) fn section(&self, name: &str) -> &Section {
+        self.sections
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap_or(&self.empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            "# A comment\n\
+             \n\
+             [batteries]\n\
+             resync_interval = 1m\n\
+             \x20 # An indented comment\n\
+             [ clock ]\n\
+             format=[hour] = [minute]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.section("batteries").get("resync_interval"),
+            Some("1m")
+        );
+        // The value is the rest of the line after the first `=`.
+        assert_eq!(
+            config.section("clock").get("format"),
+            Some("[hour] = [minute]")
+        );
+        assert_eq!(config.section("clock").get("missing"), None);
+        assert_eq!(config.section("missing").get("format"), None);
+    }
+
+    #[test]
+    fn duplicate_sections() {
+        let config = Config::parse("[timer:a]\nduration = 5m\n[timer:a]\nduration = 1m\n").unwrap();
+        // All of them are kept in order, and the first one wins for a single module.
+        let durations: Vec<_> = config.sections().map(|s| s.get("duration")).collect();
+        assert_eq!(durations, [Some("5m"), Some("1m")]);
+        assert_eq!(config.section("timer:a").get("duration"), Some("5m"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            Config::parse("[clock]\nformat\n"),
+            Err(ParseError::InvalidLine(2))
+        ));
+        assert!(matches!(
+            Config::parse("# Options first\nformat = [hour]\n[clock]\n"),
+            Err(ParseError::OptionOutsideSection(2))
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("300"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 5 m "), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        for invalid in ["", "m", "-1", "1.5s", "5d", "1 2", "99999999999999999999h"] {
+            assert_eq!(parse_duration(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn duration_or() {
+        let config = Config::parse("[s]\nzero = 0ms\nvalid = 2m\ninvalid = soon\n").unwrap();
+        let section = config.section("s");
+        let default = Some(Duration::from_secs(1));
+        assert_eq!(section.duration_or("missing", default), default);
+        assert_eq!(section.duration_or("missing", None), None);
+        // Zero disables the feature.
+        assert_eq!(section.duration_or("zero", default), None);
+        assert_eq!(
+            section.duration_or("valid", default),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(section.duration_or("invalid", default), default);
+    }
+}
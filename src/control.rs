@@ -0,0 +1,281 @@
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fmt, fs};
+
+/// A command sent on the control socket. Module names are the names of their sections in the
+/// configuration file.
+pub(crate) enum Command {
+    /// Updates one module, or all of them.
+    Refresh(Option<String>),
+    /// Changes an option of a module.
+    Set {
+        module: String,
+        key: String,
+        value: String,
+    },
+    /// Returns the blocks of every module.
+    Dump,
+    /// Reads the configuration file again and creates the modules again.
+    Reload,
+}
+
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    Empty,
+    UnknownCommand,
+    WrongArguments,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::UnknownCommand => write!(f, "unknown command"),
+            ParseError::WrongArguments => write!(f, "wrong number of arguments"),
+        }
+    }
+}
+
+impl Command {
+    /// Parses a line such as `set volume muted true`. The value of `set` is the rest of the
+    /// line, so it can contain spaces.
+    pub(crate) fn parse(line: &str) -> Result<Self, ParseError> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            "" => Err(ParseError::Empty),
+            "refresh" => match args.split_whitespace().count() {
+                0 => Ok(Command::Refresh(None)),
+                1 => Ok(Command::Refresh(Some(args.to_owned()))),
+                _ => Err(ParseError::WrongArguments),
+            },
+            "set" => {
+                let mut parts = args.splitn(3, ' ');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(module), Some(key), Some(value)) if !value.trim().is_empty() => {
+                        Ok(Command::Set {
+                            module: module.to_owned(),
+                            key: key.to_owned(),
+                            value: value.trim().to_owned(),
+                        })
+                    }
+                    _ => Err(ParseError::WrongArguments),
+                }
+            }
+            "dump" | "reload" if !args.is_empty() => Err(ParseError::WrongArguments),
+            "dump" => Ok(Command::Dump),
+            "reload" => Ok(Command::Reload),
+            _ => Err(ParseError::UnknownCommand),
+        }
+    }
+}
+
+/// Returns `$XDG_RUNTIME_DIR/gstatus.sock`.
+pub(crate) fn socket_path() -> Option<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(d) if !d.is_empty() => Some(PathBuf::from(d).join("gstatus.sock")),
+        _ => None,
+    }
+}
+
+/// Listens for clients on the control socket.
+pub(crate) struct Server {
+    listener: UnixListener,
+}
+
+impl Server {
+    /// Binds the socket. A socket left behind by a previous instance is replaced, but not one
+    /// that another instance is still listening on.
+    pub(crate) fn bind(path: &Path) -> io::Result<Self> {
+        let listener = match UnixListener::bind(path) {
+            Ok(l) => l,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(err);
+                }
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            Err(err) => return Err(err),
+        };
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    /// Accepts a pending client. Returns `None` if there is none.
+    pub(crate) fn accept(&self) -> io::Result<Option<Connection>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true)?;
+                Ok(Some(Connection {
+                    stream,
+                    buf: Vec::new(),
+                }))
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// A client of the control socket. It sends one command on a line and receives the reply until
+/// the end of the stream.
+pub(crate) struct Connection {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    /// The maximum length of a command.
+    const MAX_LEN: usize = 4096;
+    /// How long each write of the reply may wait for the client to read.
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Reads what is available. Returns the command once its line is complete, or when the
+    /// client stopped writing.
+    pub(crate) fn read(&mut self) -> io::Result<Option<String>> {
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    if self.buf.is_empty() {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    break;
+                }
+                Ok(read) => {
+                    self.buf.extend_from_slice(&chunk[..read]);
+                    if self.buf.contains(&b'\n') {
+                        break;
+                    }
+                    if self.buf.len() > Connection::MAX_LEN {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "command is too long",
+                        ));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        let line = self.buf.split(|b| *b == b'\n').next().unwrap();
+        Ok(Some(String::from_utf8_lossy(line).into_owned()))
+    }
+
+    /// Sends the reply. The reply of `dump` may not fit in the socket buffer, so the socket
+    /// blocks, which blocks the event loop while a write waits for up to `REPLY_TIMEOUT`.
+    pub(crate) fn reply(&mut self, reply: &str) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        self.stream
+            .set_write_timeout(Some(Connection::REPLY_TIMEOUT))?;
+        self.stream.write_all(reply.as_bytes())
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// Sends a command to the running instance and returns its reply.
+pub(crate) fn send(path: &Path, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn parse() {
+        assert!(matches!(
+            Command::parse("refresh\n"),
+            Ok(Command::Refresh(None))
+        ));
+        assert!(matches!(
+            Command::parse("  refresh   volume "),
+            Ok(Command::Refresh(Some(m))) if m == "volume"
+        ));
+        assert!(matches!(
+            Command::parse("set timer:tea duration 5m"),
+            Ok(Command::Set { module, key, value })
+                if module == "timer:tea" && key == "duration" && value == "5m"
+        ));
+        // The value is the rest of the line.
+        assert!(matches!(
+            Command::parse("set module key  a long  value \n"),
+            Ok(Command::Set { value, .. }) if value == "a long  value"
+        ));
+        assert!(matches!(Command::parse("dump"), Ok(Command::Dump)));
+        assert!(matches!(Command::parse("reload"), Ok(Command::Reload)));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(Command::parse(" \n"), Err(ParseError::Empty)));
+        assert!(matches!(
+            Command::parse("restart"),
+            Err(ParseError::UnknownCommand)
+        ));
+        assert!(matches!(
+            Command::parse("refresh a b"),
+            Err(ParseError::WrongArguments)
+        ));
+        assert!(matches!(
+            Command::parse("set volume muted"),
+            Err(ParseError::WrongArguments)
+        ));
+        assert!(matches!(
+            Command::parse("set volume muted  "),
+            Err(ParseError::WrongArguments)
+        ));
+        assert!(matches!(
+            Command::parse("dump x"),
+            Err(ParseError::WrongArguments)
+        ));
+        assert!(matches!(
+            Command::parse("reload now"),
+            Err(ParseError::WrongArguments)
+        ));
+    }
+
+    #[test]
+    fn reply_larger_than_the_socket_buffer() {
+        let (stream, mut client) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut connection = Connection {
+            stream,
+            buf: Vec::new(),
+        };
+        let reader = thread::spawn(move || {
+            let mut reply = String::new();
+            client.read_to_string(&mut reply).unwrap();
+            reply
+        });
+        let reply = "clock: 12:00\n".repeat(100_000);
+        connection.reply(&reply).unwrap();
+        drop(connection);
+        assert_eq!(reader.join().unwrap(), reply);
+    }
+}
@@ -0,0 +1,907 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fmt, io, mem};
+
+// See the D-Bus specification.
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+const FIELD_UNIX_FDS: u8 = 9;
+
+/// The length of the fixed part of a message header.
+const FIXED_HEADER_LEN: usize = 16;
+
+/// Bigger messages are rejected.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn align(len: usize, alignment: usize) -> usize {
+    (len + alignment - 1) & !(alignment - 1)
+}
+
+/// Returns the path of the system bus socket from `$DBUS_SYSTEM_BUS_ADDRESS`, such as
+/// `unix:path=/run/dbus/system_bus_socket`, or its default path.
+pub(crate) fn system_bus_path() -> PathBuf {
+    let address = env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_default();
+    // The address is a list of alternatives. Only the Unix socket paths are supported.
+    for alternative in address.split(';') {
+        let params = match alternative.strip_prefix("unix:") {
+            Some(p) => p,
+            None => continue,
+        };
+        for param in params.split(',') {
+            if let Some(path) = param.strip_prefix("path=") {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    PathBuf::from("/run/dbus/system_bus_socket")
+}
+
+/// Returns the first complete type of a signature, and the rest of the signature.
+fn split_type(sig: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let invalid = || invalid_data("invalid D-Bus signature");
+    let len = match *sig.first().ok_or_else(invalid)? {
+        b'a' => 1 + split_type(&sig[1..])?.0.len(),
+        open @ (b'(' | b'{') => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut rest = &sig[1..];
+            let mut len = 1;
+            while *rest.first().ok_or_else(invalid)? != close {
+                let (t, r) = split_type(rest)?;
+                len += t.len();
+                rest = r;
+            }
+            len + 1
+        }
+        _ => 1,
+    };
+    Ok(sig.split_at(len))
+}
+
+/// A value read from a message.
+pub(crate) enum Value {
+    Bool(bool),
+    /// A signed integer of any size.
+    Int(i64),
+    /// An unsigned integer of any size, or the index of a file descriptor.
+    Uint(u64),
+    Double(f64),
+    /// A string, an object path or a signature.
+    Str(String),
+    Array(Vec<Value>),
+    /// A struct or a dictionary entry.
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Uint(n) => u32::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn fields(&self) -> Option<&[Value]> {
+        match self {
+            Value::Struct(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, values: &[Value], open, close| {
+            write!(f, "{}", open)?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            write!(f, "{}", close)
+        };
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Uint(n) => write!(f, "{}", n),
+            Value::Double(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Array(values) => list(f, values, '[', ']'),
+            Value::Struct(values) => list(f, values, '(', ')'),
+            Value::Variant(value) => write!(f, "<{}>", value),
+        }
+    }
+}
+
+/// Reads the values of a message. Alignments are relative to the start of the message.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    is_big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn align(&mut self, alignment: usize) -> io::Result<()> {
+        self.pos = align(self.pos, alignment);
+        if self.pos > self.buf.len() {
+            return Err(invalid_data("truncated D-Bus message"));
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| invalid_data("truncated D-Bus message"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Reads an unsigned integer of 1, 2, 4 or 8 bytes.
+    fn uint(&mut self, size: usize) -> io::Result<u64> {
+        self.align(size)?;
+        let mut bytes = [0u8; 8];
+        let src = self.bytes(size)?;
+        if self.is_big_endian {
+            bytes[8 - size..].copy_from_slice(src);
+            Ok(u64::from_be_bytes(bytes))
+        } else {
+            bytes[..size].copy_from_slice(src);
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::try_from(self.uint(4)?).unwrap())
+    }
+
+    /// Reads a string whose length is an integer of `len_size` bytes, followed by a NUL byte.
+    fn string(&mut self, len_size: usize) -> io::Result<String> {
+        let len = usize::try_from(self.uint(len_size)?).unwrap();
+        let bytes = self.bytes(len)?;
+        self.bytes(1)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("invalid D-Bus string"))
+    }
+
+    /// Reads a value of a single complete type.
+    fn value(&mut self, sig: &[u8]) -> io::Result<Value> {
+        // Sign-extends an integer of `size` bytes.
+        let int = |n: u64, size: usize| {
+            let shift = 64 - size * 8;
+            ((n << shift) as i64) >> shift
+        };
+        let value = match sig[0] {
+            b'y' => Value::Uint(self.uint(1)?),
+            b'b' => Value::Bool(self.uint(4)? != 0),
+            b'n' => Value::Int(int(self.uint(2)?, 2)),
+            b'q' => Value::Uint(self.uint(2)?),
+            b'i' => Value::Int(int(self.uint(4)?, 4)),
+            b'u' | b'h' => Value::Uint(self.uint(4)?),
+            b'x' => Value::Int(int(self.uint(8)?, 8)),
+            b't' => Value::Uint(self.uint(8)?),
+            b'd' => Value::Double(f64::from_bits(self.uint(8)?)),
+            b's' | b'o' => Value::Str(self.string(4)?),
+            b'g' => Value::Str(self.string(1)?),
+            b'v' => {
+                let sig = self.string(1)?;
+                let (t, rest) = split_type(sig.as_bytes())?;
+                if !rest.is_empty() {
+                    return Err(invalid_data("invalid D-Bus variant"));
+                }
+                Value::Variant(Box::new(self.value(t)?))
+            }
+            b'a' => {
+                let len = usize::try_from(self.u32()?).unwrap();
+                let element = &sig[1..];
+                // The padding before the first element is not part of the length.
+                let alignment = match element[0] {
+                    b'x' | b't' | b'd' | b'(' | b'{' => 8,
+                    b'n' | b'q' => 2,
+                    b'y' | b'g' | b'v' => 1,
+                    _ => 4,
+                };
+                self.align(alignment)?;
+                let end = self.pos + len;
+                if end > self.buf.len() {
+                    return Err(invalid_data("truncated D-Bus message"));
+                }
+                let mut values = Vec::new();
+                while self.pos < end {
+                    values.push(self.value(element)?);
+                }
+                Value::Array(values)
+            }
+            b'(' | b'{' => {
+                self.align(8)?;
+                let mut fields = &sig[1..sig.len() - 1];
+                let mut values = Vec::new();
+                while !fields.is_empty() {
+                    let (t, rest) = split_type(fields)?;
+                    values.push(self.value(t)?);
+                    fields = rest;
+                }
+                Value::Struct(values)
+            }
+            _ => return Err(invalid_data("unsupported D-Bus type")),
+        };
+        Ok(value)
+    }
+}
+
+/// Writes the values of a message in little endian.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, alignment: usize) {
+        self.buf.resize(align(self.buf.len(), alignment), 0);
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(u32::try_from(s.len()).unwrap());
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.u8(u8::try_from(s.len()).unwrap());
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes a header field whose value is a string of type `sig`.
+    fn str_field(&mut self, code: u8, sig: &str, value: &str) {
+        self.align(8);
+        self.u8(code);
+        self.signature(sig);
+        match sig {
+            "g" => self.signature(value),
+            _ => self.str(value),
+        }
+    }
+}
+
+/// A reply to a method call.
+pub(crate) struct Reply {
+    pub body: Vec<Value>,
+    /// The file descriptors sent with the reply. `h` values are indices in it.
+    pub fds: Vec<File>,
+}
+
+/// A received message, with the header fields that matter to method calls.
+struct Message {
+    kind: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<Value>,
+    fds: Vec<File>,
+}
+
+/// A connection to a message bus. Method calls block until their reply arrives, or until a read
+/// or a write waits longer than `TIMEOUT`. The caller, and the event loop it runs in, is stalled
+/// meanwhile. Other messages, such as signals, are ignored.
+pub(crate) struct Connection {
+    stream: UnixStream,
+    /// The data received but not parsed yet.
+    buf: Vec<u8>,
+    /// The file descriptors received but not given to a message yet.
+    fds: VecDeque<File>,
+    serial: u32,
+    /// Whether a call failed in a way that may have left the connection unusable, such as a
+    /// closed socket or a reply that was not entirely read.
+    is_broken: bool,
+}
+
+impl Connection {
+    /// How long a read or a write on the socket may block.
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Connects to the bus, authenticates as the current user, and registers on the bus.
+    pub(crate) fn open(path: &std::path::Path) -> io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Connection::TIMEOUT))?;
+        stream.set_write_timeout(Some(Connection::TIMEOUT))?;
+        let mut r = Self {
+            stream,
+            buf: Vec::new(),
+            fds: VecDeque::new(),
+            serial: 0,
+            is_broken: false,
+        };
+        r.authenticate()?;
+        r.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        )?;
+        Ok(r)
+    }
+
+    /// Sends a line of the authentication protocol and returns the reply line.
+    fn auth_command(&mut self, command: &str) -> io::Result<String> {
+        self.stream.write_all(command.as_bytes())?;
+        self.stream.write_all(b"\r\n")?;
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<u8> = self.buf.drain(..end + 2).collect();
+                return Ok(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+            self.recv()?;
+        }
+    }
+
+    fn authenticate(&mut self) -> io::Result<()> {
+        // The server knows the credentials of the socket. The identity is the user ID in decimal,
+        // hex-encoded.
+        self.stream.write_all(b"\0")?;
+        let uid = unsafe { libc::geteuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        let reply = self.auth_command(&format!("AUTH EXTERNAL {}", hex))?;
+        if !reply.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("D-Bus authentication failed: {}", reply),
+            ));
+        }
+        // File descriptors are only received if they were negotiated.
+        let reply = self.auth_command("NEGOTIATE_UNIX_FD")?;
+        if reply != "AGREE_UNIX_FD" {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the bus doesn't pass file descriptors",
+            ));
+        }
+        self.stream.write_all(b"BEGIN\r\n")
+    }
+
+    /// Receives data and file descriptors.
+    fn recv(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        // Room for a few file descriptors, aligned for the control message headers.
+        let mut control = [0u64; 16];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control);
+        let ret =
+            unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the bus didn't reply",
+                ));
+            }
+            return Err(err);
+        }
+        if ret == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+                let data = unsafe { libc::CMSG_DATA(cmsg) } as *const libc::c_int;
+                let len = header.cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize;
+                for i in 0..len / mem::size_of::<libc::c_int>() {
+                    let fd = unsafe { data.add(i).read_unaligned() };
+                    self.fds.push_back(unsafe { File::from_raw_fd(fd) });
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        self.buf
+            .extend_from_slice(&buf[..usize::try_from(ret).unwrap()]);
+        Ok(())
+    }
+
+    /// Parses the first message of the buffer. Returns `None` if it is incomplete.
+    fn parse_message(&mut self) -> io::Result<Option<Message>> {
+        if self.buf.len() < FIXED_HEADER_LEN {
+            return Ok(None);
+        }
+        let is_big_endian = match self.buf[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(invalid_data("invalid D-Bus endianness")),
+        };
+        let mut r = Reader {
+            buf: &self.buf,
+            pos: 4,
+            is_big_endian,
+        };
+        let body_len = usize::try_from(r.u32()?).unwrap();
+        r.u32()?;
+        let fields_len = usize::try_from(r.u32()?).unwrap();
+        let body_start = align(FIXED_HEADER_LEN + fields_len, 8);
+        let len = body_start + body_len;
+        if len > MAX_MESSAGE_LEN {
+            return Err(invalid_data("D-Bus message is too long"));
+        }
+        if self.buf.len() < len {
+            return Ok(None);
+        }
+
+        let mut r = Reader {
+            buf: &self.buf[..len],
+            pos: 0,
+            is_big_endian,
+        };
+        let kind = self.buf[1];
+        let mut reply_serial = None;
+        let mut error_name = None;
+        let mut signature = String::new();
+        let mut fd_count = 0;
+        r.pos = FIXED_HEADER_LEN - 4;
+        let fields = r.value(b"a(yv)")?;
+        for field in fields.as_array().unwrap() {
+            let field = field.fields().unwrap();
+            let value = match &field[1] {
+                Value::Variant(v) => v,
+                _ => unreachable!(),
+            };
+            match field[0].as_u32() {
+                Some(code) if code == u32::from(FIELD_REPLY_SERIAL) => {
+                    reply_serial = value.as_u32()
+                }
+                Some(code) if code == u32::from(FIELD_ERROR_NAME) => {
+                    error_name = value.as_str().map(str::to_owned)
+                }
+                Some(code) if code == u32::from(FIELD_SIGNATURE) => {
+                    signature = value.as_str().unwrap_or_default().to_owned()
+                }
+                Some(code) if code == u32::from(FIELD_UNIX_FDS) => {
+                    fd_count = value.as_u32().unwrap_or(0)
+                }
+                _ => {}
+            }
+        }
+        r.pos = body_start;
+        let mut body = Vec::new();
+        let mut sig = signature.as_bytes();
+        while !sig.is_empty() {
+            let (t, rest) = split_type(sig)?;
+            body.push(r.value(t)?);
+            sig = rest;
+        }
+        self.buf.drain(..len);
+        let fd_count = usize::try_from(fd_count).unwrap().min(self.fds.len());
+        let fds = self.fds.drain(..fd_count).collect();
+        Ok(Some(Message {
+            kind,
+            reply_serial,
+            error_name,
+            body,
+            fds,
+        }))
+    }
+
+    /// Returns `true` if the connection must be opened again. An error reply doesn't break it.
+    pub(crate) fn is_broken(&self) -> bool {
+        self.is_broken
+    }
+
+    /// Calls a method whose arguments are all strings, and waits for its reply.
+    pub(crate) fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[&str],
+    ) -> io::Result<Reply> {
+        self.serial = self.serial.wrapping_add(1).max(1);
+        let serial = self.serial;
+
+        let mut body = Writer::default();
+        for arg in args {
+            body.str(arg);
+        }
+        let mut w = Writer::default();
+        w.u8(b'l');
+        w.u8(METHOD_CALL);
+        w.u8(0);
+        w.u8(1);
+        w.u32(u32::try_from(body.buf.len()).unwrap());
+        w.u32(serial);
+        // The length of the header fields is written once they are.
+        w.u32(0);
+        let fields_start = w.buf.len();
+        w.str_field(FIELD_PATH, "o", path);
+        w.str_field(FIELD_INTERFACE, "s", interface);
+        w.str_field(FIELD_MEMBER, "s", member);
+        w.str_field(FIELD_DESTINATION, "s", destination);
+        if !args.is_empty() {
+            w.str_field(FIELD_SIGNATURE, "g", &"s".repeat(args.len()));
+        }
+        let fields_len = u32::try_from(w.buf.len() - fields_start).unwrap();
+        w.buf[fields_start - 4..fields_start].copy_from_slice(&fields_len.to_le_bytes());
+        w.align(8);
+        w.buf.extend_from_slice(&body.buf);
+        let msg = match self.send(&w.buf, serial) {
+            Ok(m) => m,
+            Err(err) => {
+                self.is_broken = true;
+                return Err(err);
+            }
+        };
+        if msg.kind == ERROR {
+            let name = msg.error_name.unwrap_or_default();
+            let message = msg.body.first().and_then(Value::as_str).unwrap_or("");
+            return Err(io::Error::other(format!("{}: {}", name, message)));
+        }
+        Ok(Reply {
+            body: msg.body,
+            fds: msg.fds,
+        })
+    }
+
+    /// Sends a method call and returns its reply, which is either a method return or an error.
+    fn send(&mut self, call: &[u8], serial: u32) -> io::Result<Message> {
+        self.stream.write_all(call)?;
+        loop {
+            let msg = match self.parse_message()? {
+                Some(m) => m,
+                None => {
+                    self.recv()?;
+                    continue;
+                }
+            };
+            if msg.reply_serial == Some(serial) && matches!(msg.kind, METHOD_RETURN | ERROR) {
+                return Ok(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::io::RawFd;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    const SIGNAL: u8 = 4;
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+        assert_eq!(ret, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    /// Reads a line of the authentication protocol, without the `\r\n`.
+    fn read_line(stream: &mut UnixStream) -> String {
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut b = [0u8];
+            stream.read_exact(&mut b).unwrap();
+            line.push(b[0]);
+        }
+        line.truncate(line.len() - 2);
+        String::from_utf8(line).unwrap()
+    }
+
+    /// Reads a method call and returns its serial and its member.
+    fn read_call(stream: &mut UnixStream) -> (u32, String) {
+        let mut buf = vec![0u8; FIXED_HEADER_LEN];
+        stream.read_exact(&mut buf).unwrap();
+        let u32_at =
+            |buf: &[u8], i: usize| u32::from_le_bytes(<[u8; 4]>::try_from(&buf[i..i + 4]).unwrap());
+        let body_len = u32_at(&buf, 4) as usize;
+        let fields_len = u32_at(&buf, 12) as usize;
+        buf.resize(align(FIXED_HEADER_LEN + fields_len, 8) + body_len, 0);
+        stream.read_exact(&mut buf[FIXED_HEADER_LEN..]).unwrap();
+        assert_eq!(buf[1], METHOD_CALL);
+        let mut r = Reader {
+            buf: &buf,
+            pos: FIXED_HEADER_LEN - 4,
+            is_big_endian: false,
+        };
+        let fields = r.value(b"a(yv)").unwrap();
+        let member = fields
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f.fields().unwrap())
+            .find(|f| f[0].as_u32() == Some(u32::from(FIELD_MEMBER)))
+            .and_then(|f| match &f[1] {
+                Value::Variant(v) => v.as_str().map(str::to_owned),
+                _ => None,
+            })
+            .unwrap();
+        (u32_at(&buf, 8), member)
+    }
+
+    /// Sends a message from the bus, with a body that is already marshalled and file descriptors.
+    fn send(
+        stream: &UnixStream,
+        kind: u8,
+        reply_serial: Option<u32>,
+        error_name: Option<&str>,
+        (signature, body): (&str, &[u8]),
+        fds: &[RawFd],
+    ) {
+        let mut w = Writer::default();
+        w.u8(b'l');
+        w.u8(kind);
+        w.u8(0);
+        w.u8(1);
+        w.u32(u32::try_from(body.len()).unwrap());
+        w.u32(1);
+        w.u32(0);
+        let fields_start = w.buf.len();
+        let u32_field = |w: &mut Writer, code, n| {
+            w.align(8);
+            w.u8(code);
+            w.signature("u");
+            w.u32(n);
+        };
+        if let Some(serial) = reply_serial {
+            u32_field(&mut w, FIELD_REPLY_SERIAL, serial);
+        }
+        if !fds.is_empty() {
+            u32_field(&mut w, FIELD_UNIX_FDS, u32::try_from(fds.len()).unwrap());
+        }
+        if let Some(name) = error_name {
+            w.str_field(FIELD_ERROR_NAME, "s", name);
+        }
+        if !signature.is_empty() {
+            w.str_field(FIELD_SIGNATURE, "g", signature);
+        }
+        let fields_len = u32::try_from(w.buf.len() - fields_start).unwrap();
+        w.buf[fields_start - 4..fields_start].copy_from_slice(&fields_len.to_le_bytes());
+        w.align(8);
+        w.buf.extend_from_slice(body);
+
+        let mut iov = libc::iovec {
+            iov_base: w.buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: w.buf.len(),
+        };
+        let mut control = [0u64; 16];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            let len = mem::size_of_val(fds) as u32;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = unsafe { libc::CMSG_SPACE(len) } as usize;
+            unsafe {
+                let cmsg = &mut *libc::CMSG_FIRSTHDR(&msg);
+                cmsg.cmsg_level = libc::SOL_SOCKET;
+                cmsg.cmsg_type = libc::SCM_RIGHTS;
+                cmsg.cmsg_len = libc::CMSG_LEN(len) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *mut libc::c_int;
+                data.copy_from_nonoverlapping(fds.as_ptr(), fds.len());
+            }
+        }
+        let ret = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+        assert_eq!(usize::try_from(ret).unwrap(), w.buf.len());
+    }
+
+    fn reply(stream: &UnixStream, serial: u32, body: (&str, &[u8]), fds: &[RawFd]) {
+        send(stream, METHOD_RETURN, Some(serial), None, body, fds);
+    }
+
+    /// Marshals the reply of `ListInhibitors`, an array of `(ssssuu)`.
+    fn inhibitors(entries: &[[&str; 4]], pid: u32) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u32(0);
+        w.align(8);
+        let start = w.buf.len();
+        for entry in entries {
+            w.align(8);
+            for s in entry {
+                w.str(s);
+            }
+            w.u32(1000);
+            w.u32(pid);
+        }
+        let len = u32::try_from(w.buf.len() - start).unwrap();
+        w.buf[..4].copy_from_slice(&len.to_le_bytes());
+        w.buf
+    }
+
+    /// Marshals the reply of `ListSessions`, an array of `(susso)`.
+    fn sessions(entries: &[(&str, u32, &str, &str, &str)]) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u32(0);
+        w.align(8);
+        let start = w.buf.len();
+        for (id, uid, user, seat, path) in entries {
+            w.align(8);
+            w.str(id);
+            w.u32(*uid);
+            w.str(user);
+            w.str(seat);
+            w.str(path);
+        }
+        let len = u32::try_from(w.buf.len() - start).unwrap();
+        w.buf[..4].copy_from_slice(&len.to_le_bytes());
+        w.buf
+    }
+
+    /// Plays logind and the bus for the calls of `call_logind`.
+    fn serve(listener: UnixListener) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut nul = [0u8];
+        stream.read_exact(&mut nul).unwrap();
+        assert_eq!(nul, [0]);
+        let uid = unsafe { libc::geteuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(read_line(&mut stream), format!("AUTH EXTERNAL {}", hex));
+        stream.write_all(b"OK 0123456789abcdef\r\n").unwrap();
+        assert_eq!(read_line(&mut stream), "NEGOTIATE_UNIX_FD");
+        stream.write_all(b"AGREE_UNIX_FD\r\n").unwrap();
+        assert_eq!(read_line(&mut stream), "BEGIN");
+
+        let (serial, member) = read_call(&mut stream);
+        assert_eq!(member, "Hello");
+        // The bus also sends signals, which are skipped.
+        let mut name = Writer::default();
+        name.str(":1.42");
+        send(&stream, SIGNAL, None, None, ("s", &name.buf), &[]);
+        reply(&stream, serial, ("s", &name.buf), &[]);
+
+        let (serial, member) = read_call(&mut stream);
+        assert_eq!(member, "ListSessions");
+        let body = sessions(&[
+            (
+                "1",
+                1000,
+                "alice",
+                "seat0",
+                "/org/freedesktop/login1/session/_31",
+            ),
+            ("c2", 1001, "bob", "", "/org/freedesktop/login1/session/c2"),
+        ]);
+        reply(&stream, serial, ("a(susso)", &body), &[]);
+
+        let (serial, member) = read_call(&mut stream);
+        assert_eq!(member, "ListInhibitors");
+        let body = inhibitors(
+            &[
+                ["sleep", "NetworkManager", "wait", "delay"],
+                ["idle:sleep", "firefox", "video", "block"],
+            ],
+            1234,
+        );
+        reply(&stream, serial, ("a(ssssuu)", &body), &[]);
+
+        let (serial, member) = read_call(&mut stream);
+        assert_eq!(member, "Inhibit");
+        let (lock, _) = pipe();
+        let mut index = Writer::default();
+        index.u32(0);
+        reply(&stream, serial, ("h", &index.buf), &[lock.as_raw_fd()]);
+
+        let (serial, member) = read_call(&mut stream);
+        assert_eq!(member, "Inhibit");
+        let mut message = Writer::default();
+        message.str("denied");
+        let name = Some("org.freedesktop.DBus.Error.AccessDenied");
+        send(&stream, ERROR, Some(serial), name, ("s", &message.buf), &[]);
+
+        // Then the bus goes away.
+        read_call(&mut stream);
+    }
+
+    #[test]
+    fn call_logind() {
+        let path = env::temp_dir().join(format!("gstatus-test-dbus-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let bus = thread::spawn(move || serve(listener));
+        let mut connection = Connection::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let reply = connection
+            .call("org.freedesktop.login1", "/", "i", "ListSessions", &[])
+            .unwrap();
+        let entries = reply.body[0].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let fields = entries[1].fields().unwrap();
+        assert_eq!(fields[0].as_str(), Some("c2"));
+        assert_eq!(fields[1].as_u32(), Some(1001));
+        assert_eq!(fields[2].as_str(), Some("bob"));
+        assert_eq!(fields[3].as_str(), Some(""));
+        assert_eq!(
+            fields[4].as_str(),
+            Some("/org/freedesktop/login1/session/c2")
+        );
+
+        let reply = connection
+            .call("org.freedesktop.login1", "/", "i", "ListInhibitors", &[])
+            .unwrap();
+        let entries = reply.body[0].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let fields = entries[1].fields().unwrap();
+        assert_eq!(fields[0].as_str(), Some("idle:sleep"));
+        assert_eq!(fields[1].as_str(), Some("firefox"));
+        assert_eq!(fields[3].as_str(), Some("block"));
+        assert_eq!(fields[5].as_u32(), Some(1234));
+
+        let args = ["idle", "gstatus", "test", "block"];
+        let reply = connection
+            .call("org.freedesktop.login1", "/", "i", "Inhibit", &args)
+            .unwrap();
+        assert_eq!(reply.body[0].as_u32(), Some(0));
+        assert_eq!(reply.fds.len(), 1);
+        let flags = unsafe { libc::fcntl(reply.fds[0].as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags, libc::FD_CLOEXEC);
+
+        let err = connection
+            .call("org.freedesktop.login1", "/", "i", "Inhibit", &args)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "org.freedesktop.DBus.Error.AccessDenied: denied"
+        );
+        assert!(!connection.is_broken());
+
+        let err = connection
+            .call("org.freedesktop.login1", "/", "i", "ListInhibitors", &[])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(connection.is_broken());
+        bus.join().unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+use std::{io, iter, process};
+
+use crate::config;
+use crate::dbus::{self, Connection, Value};
+use crate::module::{self, Block, ClickEvent, Module};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const PATH: &str = "/org/freedesktop/login1";
+const INTERFACE: &str = "org.freedesktop.login1.Manager";
+
+/// A lock that delays or blocks sleep, shutdown or idleness, as listed by logind.
+struct Inhibitor {
+    /// The colon-separated list of what is inhibited, such as `sleep:idle`.
+    what: String,
+    who: String,
+    pid: u32,
+}
+
+impl Inhibitor {
+    /// Parses an `(ssssuu)` entry of `ListInhibitors`: what, who, why, mode, uid and pid.
+    /// Returns `None` for an entry that only delays.
+    fn parse(value: &Value) -> Option<Self> {
+        let fields = value.fields()?;
+        if fields.len() != 6 || fields[3].as_str()? != "block" {
+            return None;
+        }
+        Some(Self {
+            what: fields[0].as_str()?.to_owned(),
+            who: fields[1].as_str()?.to_owned(),
+            pid: fields[5].as_u32()?,
+        })
+    }
+}
+
+/// A session of a logged-in user, as listed by logind.
+struct Session {
+    user: String,
+}
+
+impl Session {
+    /// Parses a `(susso)` entry of `ListSessions`: id, uid, user name, seat and object path.
+    fn parse(value: &Value) -> Option<Self> {
+        let fields = value.fields()?;
+        if fields.len() != 5 {
+            return None;
+        }
+        Some(Self {
+            user: fields[2].as_str()?.to_owned(),
+        })
+    }
+}
+
+/// Shows the logged-in users, such as `Users: alice, bob`, and what blocks sleep, shutdown or
+/// idleness, such as `sleep blocked by firefox`. The left button toggles an inhibitor of idleness
+/// held by gstatus.
+///
+/// logind is called synchronously: every update blocks the event loop, and with it the other
+/// modules, until logind replies. A call gives up after `Connection::TIMEOUT`, and a broken
+/// connection is opened again before retrying once, so an update stalls the bar for a few
+/// seconds at worst when the bus hangs. The interval should stay well above that.
+pub(crate) struct Inhibitors {
+    connection: Connection,
+    /// The reason given to logind for our inhibitor.
+    why: String,
+    interval: Duration,
+    /// The sessions of all users, including ours.
+    sessions: Vec<Session>,
+    /// The inhibitors of other processes.
+    inhibitors: Vec<Inhibitor>,
+    /// The file descriptor of our inhibitor. Closing it releases the inhibitor.
+    idle_lock: Option<File>,
+    timeout: Instant,
+}
+
+impl Inhibitors {
+    const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+    const DEFAULT_WHY: &'static str = "Inhibited from the status bar";
+
+    /// Creates the module of the `[inhibitors]` section, and connects to the system bus.
+    pub(crate) fn new(config: &config::Section) -> io::Result<Self> {
+        let mut r = Self {
+            connection: Connection::open(&dbus::system_bus_path())?,
+            why: config
+                .get("why")
+                .unwrap_or(Inhibitors::DEFAULT_WHY)
+                .to_owned(),
+            interval: config
+                .duration_or("interval", Some(Inhibitors::DEFAULT_INTERVAL))
+                .unwrap_or(Inhibitors::DEFAULT_INTERVAL),
+            sessions: Vec::new(),
+            inhibitors: Vec::new(),
+            idle_lock: None,
+            timeout: Instant::now(),
+        };
+        r.list()?;
+        Ok(r)
+    }
+
+    /// Calls a method of logind, blocking until it replies. If the connection to the bus broke,
+    /// for example because the bus restarted, it connects again and retries once.
+    fn call(&mut self, member: &str, args: &[&str]) -> io::Result<dbus::Reply> {
+        if !self.connection.is_broken() {
+            match self
+                .connection
+                .call(DESTINATION, PATH, INTERFACE, member, args)
+            {
+                Err(err) if self.connection.is_broken() => {
+                    eprintln!("lost the connection to the system bus: {err}");
+                }
+                result => return result,
+            }
+        }
+        self.connection = Connection::open(&dbus::system_bus_path())?;
+        self.connection
+            .call(DESTINATION, PATH, INTERFACE, member, args)
+    }
+
+    fn list(&mut self) -> io::Result<()> {
+        self.timeout = Instant::now() + self.interval;
+        let reply = self.call("ListSessions", &[])?;
+        let entries = reply
+            .body
+            .first()
+            .and_then(Value::as_array)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid session list"))?;
+        self.sessions = entries.iter().filter_map(Session::parse).collect();
+
+        let reply = self.call("ListInhibitors", &[])?;
+        let entries = reply
+            .body
+            .first()
+            .and_then(Value::as_array)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid inhibitor list"))?;
+        // Our inhibitor is shown from its file descriptor, which is up to date even when logind
+        // didn't notice yet that it was closed.
+        let pid = process::id();
+        self.inhibitors = entries
+            .iter()
+            .filter_map(Inhibitor::parse)
+            .filter(|inhibitor| inhibitor.pid != pid)
+            .collect();
+        Ok(())
+    }
+
+    fn inhibit_idle(&mut self) -> io::Result<()> {
+        if self.idle_lock.is_some() {
+            return Ok(());
+        }
+        let why = self.why.clone();
+        let mut reply = self.call("Inhibit", &["idle", "gstatus", &why, "block"])?;
+        // The reply is the index of the file descriptor.
+        let index = reply
+            .body
+            .first()
+            .and_then(Value::as_u32)
+            .and_then(|i| usize::try_from(i).ok())
+            .filter(|i| *i < reply.fds.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid inhibitor"))?;
+        self.idle_lock = Some(reply.fds.swap_remove(index));
+        Ok(())
+    }
+
+    fn set_idle(&mut self, inhibit: bool) {
+        if !inhibit {
+            self.idle_lock = None;
+        } else if let Err(err) = self.inhibit_idle() {
+            eprintln!("failed to inhibit idleness: {err}");
+        }
+    }
+}
+
+impl Module for Inhibitors {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        // A user with several sessions, such as a graphical one and SSH, is shown once.
+        let mut users: Vec<&str> = self.sessions.iter().map(|s| s.user.as_str()).collect();
+        users.sort_unstable();
+        users.dedup();
+        let sessions = Block {
+            text: if users.is_empty() {
+                "Nobody logged in".to_owned()
+            } else {
+                format!("Users: {}", users.join(", "))
+            },
+            short_text: Some(format!("Sessions: {}", self.sessions.len())),
+            ..Default::default()
+        };
+        let own = self.idle_lock.as_ref().map(|_| ("idle", "gstatus"));
+        let others = self
+            .inhibitors
+            .iter()
+            .map(|inhibitor| (inhibitor.what.as_str(), inhibitor.who.as_str()));
+        let parts: Vec<String> = own
+            .into_iter()
+            .chain(others)
+            .map(|(what, who)| format!("{} blocked by {}", what.replace(':', "/"), who))
+            .collect();
+        let block = if parts.is_empty() {
+            Block {
+                text: "Nothing inhibited".to_owned(),
+                short_text: Some("Inhibited: 0".to_owned()),
+                ..Default::default()
+            }
+        } else {
+            Block {
+                text: parts.join(", "),
+                short_text: Some(format!("Inhibited: {}", parts.len())),
+                ..Default::default()
+            }
+        };
+        Box::new(iter::once(sessions).chain(iter::once(block)))
+    }
+
+    fn update(&mut self) -> bool {
+        if let Err(err) = self.list() {
+            eprintln!("failed to list the inhibitors: {err}");
+            return false;
+        }
+        true
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        Some(self.timeout)
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        if event.button != 1 {
+            return false;
+        }
+        self.set_idle(self.idle_lock.is_none());
+        true
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        match key {
+            "idle" => match value {
+                "true" => self.set_idle(true),
+                "false" => self.set_idle(false),
+                "toggle" => self.set_idle(self.idle_lock.is_none()),
+                _ => return Err(module::invalid_value(key, value)),
+            },
+            _ => return Err(module::unknown_option(key)),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn str(s: &str) -> Value {
+        Value::Str(s.to_owned())
+    }
+
+    #[test]
+    fn parse_entries() {
+        let session = Value::Struct(vec![
+            str("c2"),
+            Value::Uint(1001),
+            str("bob"),
+            str(""),
+            str("/org/freedesktop/login1/session/c2"),
+        ]);
+        assert_eq!(Session::parse(&session).unwrap().user, "bob");
+        assert!(Session::parse(&Value::Struct(vec![str("c2")])).is_none());
+
+        let inhibitor = |mode| {
+            Value::Struct(vec![
+                str("idle:sleep"),
+                str("firefox"),
+                str("video"),
+                str(mode),
+                Value::Uint(1000),
+                Value::Uint(1234),
+            ])
+        };
+        let blocking = Inhibitor::parse(&inhibitor("block")).unwrap();
+        assert_eq!(blocking.what, "idle:sleep");
+        assert_eq!(blocking.who, "firefox");
+        assert_eq!(blocking.pid, 1234);
+        assert!(Inhibitor::parse(&inhibitor("delay")).is_none());
+    }
+}
@@ -0,0 +1,369 @@
+use std::convert::TryFrom;
+
+/// A JSON value. Object members are kept in order.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the value of an object member.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= f64::from(u32::MAX) => {
+                Some(*n as u32)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    UnexpectedEnd,
+    UnexpectedChar,
+    InvalidNumber,
+    InvalidEscape,
+    TrailingCharacters,
+    TooDeep,
+}
+
+/// Parses a JSON document.
+pub(crate) fn parse(s: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        s: s.as_bytes(),
+        i: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.i != parser.s.len() {
+        return Err(ParseError::TrailingCharacters);
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    i: usize,
+    /// The number of arrays and objects that contain the current value.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Arrays and objects are parsed recursively, so their nesting is limited to keep the stack
+    /// from overflowing. Click events and widget output are much shallower.
+    const MAX_DEPTH: usize = 64;
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.s.get(self.i) {
+            self.i += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8, ParseError> {
+        self.skip_whitespace();
+        self.s.get(self.i).copied().ok_or(ParseError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), ParseError> {
+        if self.peek()? != b {
+            return Err(ParseError::UnexpectedChar);
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &[u8], value: Value) -> Result<Value, ParseError> {
+        if !self.s[self.i..].starts_with(lit) {
+            return Err(ParseError::UnexpectedChar);
+        }
+        self.i += lit.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek()? {
+            b'n' => self.literal(b"null", Value::Null),
+            b't' => self.literal(b"true", Value::Bool(true)),
+            b'f' => self.literal(b"false", Value::Bool(false)),
+            b'"' => Ok(Value::String(self.string()?)),
+            open @ (b'[' | b'{') => {
+                if self.depth == Parser::MAX_DEPTH {
+                    return Err(ParseError::TooDeep);
+                }
+                self.depth += 1;
+                let value = if open == b'[' {
+                    self.array()
+                } else {
+                    self.object()
+                };
+                self.depth -= 1;
+                value
+            }
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => Err(ParseError::UnexpectedChar),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.i += 1;
+        let mut items = Vec::new();
+        if self.peek()? == b']' {
+            self.i += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek()? {
+                b',' => self.i += 1,
+                b']' => {
+                    self.i += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(ParseError::UnexpectedChar),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.i += 1;
+        let mut members = Vec::new();
+        if self.peek()? == b'}' {
+            self.i += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            if self.peek()? != b'"' {
+                return Err(ParseError::UnexpectedChar);
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            match self.peek()? {
+                b',' => self.i += 1,
+                b'}' => {
+                    self.i += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(ParseError::UnexpectedChar),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.i;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.s.get(self.i) {
+            self.i += 1;
+        }
+        // The slice only contains ASCII characters, so it is valid UTF-8.
+        std::str::from_utf8(&self.s[start..self.i])
+            .unwrap()
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| ParseError::InvalidNumber)
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let digits = self
+            .s
+            .get(self.i..(self.i + 4))
+            .ok_or(ParseError::UnexpectedEnd)?;
+        let digits = std::str::from_utf8(digits).map_err(|_| ParseError::InvalidEscape)?;
+        let r = u32::from_str_radix(digits, 16).map_err(|_| ParseError::InvalidEscape)?;
+        self.i += 4;
+        Ok(r)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        // The opening quote was already peeked.
+        self.i += 1;
+        let mut r = Vec::new();
+        loop {
+            let b = *self.s.get(self.i).ok_or(ParseError::UnexpectedEnd)?;
+            self.i += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let e = *self.s.get(self.i).ok_or(ParseError::UnexpectedEnd)?;
+                    self.i += 1;
+                    let c = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Decode a UTF-16 surrogate pair.
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.s[self.i..].starts_with(b"\\u")
+                            {
+                                self.i += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(ParseError::InvalidEscape);
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::try_from(code).map_err(|_| ParseError::InvalidEscape)?
+                        }
+                        _ => return Err(ParseError::InvalidEscape),
+                    };
+                    let mut buf = [0u8; 4];
+                    r.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                _ => r.push(b),
+            }
+        }
+        // The input is a `str` and escapes produce valid characters, so this can't fail.
+        Ok(String::from_utf8(r).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Result<String, ParseError> {
+        match parse(s)? {
+            Value::String(s) => Ok(s),
+            other => panic!("not a string: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_escapes() {
+        assert_eq!(
+            string(r#""a\"\\\/\b\f\n\r\tb""#).unwrap(),
+            "a\"\\/\u{8}\u{c}\n\r\tb"
+        );
+        assert_eq!(string(r#""\u00e9\u20AC""#).unwrap(), "é€");
+        assert_eq!(string("\"é\"").unwrap(), "é");
+        assert!(matches!(string(r#""\x""#), Err(ParseError::InvalidEscape)));
+        assert!(matches!(
+            string(r#""\u12g4""#),
+            Err(ParseError::InvalidEscape)
+        ));
+        assert!(matches!(string(r#""\u12"#), Err(ParseError::UnexpectedEnd)));
+        assert!(matches!(string(r#""abc"#), Err(ParseError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn parse_surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "😀");
+        assert_eq!(string(r#""\uD834\uDD1E!""#).unwrap(), "𝄞!");
+        // A high surrogate must be followed by a low one, and a lone surrogate is not a
+        // character.
+        assert!(matches!(
+            string(r#""\ud83d\u0041""#),
+            Err(ParseError::InvalidEscape)
+        ));
+        assert!(matches!(
+            string(r#""\ud83d""#),
+            Err(ParseError::InvalidEscape)
+        ));
+        assert!(matches!(
+            string(r#""\ude00""#),
+            Err(ParseError::InvalidEscape)
+        ));
+    }
+
+    #[test]
+    fn parse_click_event() {
+        // As sent by i3bar 4.22 and sway, with fields that we don't use.
+        let value = parse(
+            r#"{"name":"6","instance":"0","button":3,"modifiers":["Shift","Mod4"],"x":1824,"y":10,"relative_x":12,"relative_y":10,"output_x":1812,"output_y":0,"width":80,"height":20,"scale":1.5,"extra":{"nested":[{"a":null},[true,false,-1.5e3]]}}"#,
+        )
+        .unwrap();
+        assert_eq!(value.get("name").and_then(Value::as_str), Some("6"));
+        assert_eq!(value.get("instance").and_then(Value::as_str), Some("0"));
+        assert_eq!(value.get("button").and_then(Value::as_u32), Some(3));
+        assert_eq!(
+            value.get("modifiers").and_then(Value::as_array),
+            Some(&[Value::String("Shift".into()), Value::String("Mod4".into())][..])
+        );
+        assert_eq!(value.get("scale").and_then(Value::as_u32), None);
+        assert_eq!(
+            value.get("extra").and_then(|v| v.get("nested")),
+            Some(&Value::Array(vec![
+                Value::Object(vec![("a".into(), Value::Null)]),
+                Value::Array(vec![
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Number(-1500.0)
+                ]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn reject_trailing_characters() {
+        assert!(parse(" {\"a\": [1, 2]} \n").is_ok());
+        assert!(matches!(parse("{} x"), Err(ParseError::TrailingCharacters)));
+        assert!(matches!(
+            parse("[1] [2]"),
+            Err(ParseError::TrailingCharacters)
+        ));
+        assert!(matches!(
+            parse("truex"),
+            Err(ParseError::TrailingCharacters)
+        ));
+        assert!(matches!(parse("{},"), Err(ParseError::TrailingCharacters)));
+        assert!(matches!(parse("[1,]"), Err(ParseError::UnexpectedChar)));
+        assert!(matches!(parse(""), Err(ParseError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn limit_depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(Parser::MAX_DEPTH)).is_ok());
+        assert!(matches!(
+            parse(&nested(Parser::MAX_DEPTH + 1)),
+            Err(ParseError::TooDeep)
+        ));
+        let objects = "{\"a\":".repeat(Parser::MAX_DEPTH) + "{}" + &"}".repeat(Parser::MAX_DEPTH);
+        assert!(matches!(parse(&objects), Err(ParseError::TooDeep)));
+        // This would overflow the stack without the limit.
+        assert!(matches!(
+            parse(&"[".repeat(1_000_000)),
+            Err(ParseError::TooDeep)
+        ));
+    }
+}
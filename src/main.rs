@@ -1,128 +1,825 @@
+mod backlight;
 mod batteries;
+mod click;
 mod clock;
+mod command;
+mod config;
+mod control;
+mod dbus;
+mod inhibitors;
+mod json;
 mod mem;
 mod module;
+mod netlink;
+mod pomodoro;
+mod reactor;
+mod signal;
+mod supervisor;
+mod timechange;
+mod timer;
 mod uevent;
+mod uptime;
+mod volume;
+mod vpn;
+mod widget;
+mod wifi;
+mod zoneinfo;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::convert::TryInto;
+use std::ffi::OsStr;
 use std::io;
 use std::io::Write;
-use std::time::Instant;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::{env, process};
 
-use crate::module::Module;
+use crate::module::{ClickEvent, Module};
+use crate::reactor::{Reactor, ReadyKind};
+use crate::supervisor::Supervisor;
 
+use self::backlight::*;
 use self::batteries::*;
 use self::clock::*;
+use self::command::*;
+use self::inhibitors::*;
 use self::mem::*;
+use self::pomodoro::*;
+use self::timer::*;
+use self::uptime::*;
+use self::volume::*;
+use self::vpn::*;
+use self::widget::*;
+use self::wifi::*;
 
 fn escape_json_string(s: &str) -> String {
-    s.replace('"', "\"")
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            // A raw control character would make the JSON stream invalid.
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// How long to wait for more updates before printing the blocks.
+const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(20);
+
+/// The minimum time between two status lines.
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sent by i3bar when the bar is hidden.
+const STOP_SIGNAL: libc::c_int = libc::SIGUSR1;
+
+/// Sent by i3bar when the bar is shown again.
+const CONT_SIGNAL: libc::c_int = libc::SIGUSR2;
+
+/// What a file descriptor or a deadline of the reactor belongs to.
+#[derive(Clone, Copy)]
+enum Source {
+    Module(usize),
+    Uevents,
+    Clicks,
+    Signals,
+    /// The blocks must be printed.
+    Render,
+    /// The control socket.
+    Control,
+    /// A client of the control socket.
+    Connection(u32),
+    /// The system clock was set or the system resumed.
+    ClockChanges,
+    /// The local time zone changed.
+    ZoneChanges,
+}
+
+impl Source {
+    const UEVENTS_TOKEN: reactor::Token = reactor::Token::MAX;
+    const CLICKS_TOKEN: reactor::Token = reactor::Token::MAX - 1;
+    const RENDER_TOKEN: reactor::Token = reactor::Token::MAX - 2;
+    const SIGNALS_TOKEN: reactor::Token = reactor::Token::MAX - 3;
+    const CONTROL_TOKEN: reactor::Token = reactor::Token::MAX - 4;
+    const CLOCK_CHANGES_TOKEN: reactor::Token = reactor::Token::MAX - 5;
+    const ZONE_CHANGES_TOKEN: reactor::Token = reactor::Token::MAX - 6;
+    /// Connections use the tokens above this one, and modules the tokens below it.
+    const CONNECTIONS_TOKEN: reactor::Token = 1 << 32;
+
+    fn token(self) -> reactor::Token {
+        match self {
+            Source::Module(i) => reactor::Token::try_from(i).unwrap(),
+            Source::Uevents => Source::UEVENTS_TOKEN,
+            Source::Clicks => Source::CLICKS_TOKEN,
+            Source::Render => Source::RENDER_TOKEN,
+            Source::Signals => Source::SIGNALS_TOKEN,
+            Source::Control => Source::CONTROL_TOKEN,
+            Source::Connection(id) => Source::CONNECTIONS_TOKEN + reactor::Token::from(id),
+            Source::ClockChanges => Source::CLOCK_CHANGES_TOKEN,
+            Source::ZoneChanges => Source::ZONE_CHANGES_TOKEN,
+        }
+    }
+
+    fn from_token(token: reactor::Token) -> Self {
+        match token {
+            Source::UEVENTS_TOKEN => Source::Uevents,
+            Source::CLICKS_TOKEN => Source::Clicks,
+            Source::RENDER_TOKEN => Source::Render,
+            Source::SIGNALS_TOKEN => Source::Signals,
+            Source::CONTROL_TOKEN => Source::Control,
+            Source::CLOCK_CHANGES_TOKEN => Source::ClockChanges,
+            Source::ZONE_CHANGES_TOKEN => Source::ZoneChanges,
+            i if i >= Source::CONNECTIONS_TOKEN => {
+                Source::Connection(u32::try_from(i - Source::CONNECTIONS_TOKEN).unwrap())
+            }
+            i => Source::Module(usize::try_from(i).unwrap()),
+        }
+    }
+}
+
+/// Creates all of the modules. The ones that fail are created again later.
+fn create_modules(config: &config::Config) -> Vec<Supervisor> {
+    let batteries_config = config.section("batteries").clone();
+    let backlight_config = config.section("backlight").clone();
+    let mut modules = vec![
+        Supervisor::new("wifi", "Wi-Fi", || Ok(Box::new(Wifi::new()?))),
+        Supervisor::new("vpn", "VPN", || Ok(Box::new(Vpn::new()?))),
+        Supervisor::new("mem", "Mem", || Ok(Box::new(Mem::open()?))),
+        Supervisor::new("batteries", "Batteries", move || {
+            Ok(Box::new(Batteries::new(&batteries_config)?))
+        }),
+        Supervisor::new("volume", "Volume", || Ok(Box::new(Volume::open()?))),
+        Supervisor::new("backlight", "Backlight", move || {
+            Ok(Box::new(Backlight::new(&backlight_config)?))
+        }),
+    ];
+    // The commands, the widgets, the other clocks, the timers, the pomodoro, the uptime and the
+    // inhibitors appear in the order of the file, before the clock.
+    for section in config.sections() {
+        let section_config = section.clone();
+        if let Some(name) = section.name.strip_prefix("command:") {
+            let name = name.to_owned();
+            modules.push(Supervisor::new(&section.name, &name.clone(), move || {
+                Ok(Box::new(Command::new(&name, &section_config)?))
+            }));
+        } else if let Some(name) = section.name.strip_prefix("widget:") {
+            let name = name.to_owned();
+            modules.push(Supervisor::new(&section.name, &name.clone(), move || {
+                Ok(Box::new(Widget::new(&name, &section_config)?))
+            }));
+        } else if let Some(name) = section.name.strip_prefix("clock:") {
+            let name = name.to_owned();
+            modules.push(Supervisor::new(&section.name, &name.clone(), move || {
+                Ok(Box::new(Clock::new(Some(&name), &section_config)?))
+            }));
+        } else if let Some(name) = section.name.strip_prefix("timer:") {
+            let name = name.to_owned();
+            modules.push(Supervisor::new(&section.name, &name.clone(), move || {
+                Ok(Box::new(Timer::new(&name, &section_config)))
+            }));
+        } else if section.name == "pomodoro" {
+            modules.push(Supervisor::new("pomodoro", "Pomodoro", move || {
+                Ok(Box::new(Pomodoro::new(&section_config)?))
+            }));
+        } else if section.name == "uptime" {
+            modules.push(Supervisor::new("uptime", "Uptime", || {
+                Ok(Box::new(Uptime::new()?))
+            }));
+        } else if section.name == "inhibitors" {
+            modules.push(Supervisor::new("inhibitors", "Inhibitors", move || {
+                Ok(Box::new(Inhibitors::new(&section_config)?))
+            }));
+        }
+    }
+    let clock_config = config.section("clock").clone();
+    modules.push(Supervisor::new("clock", "Clock", move || {
+        Ok(Box::new(Clock::new(None, &clock_config)?))
+    }));
+    modules
+}
+
+/// Returns how long to wait for more updates before printing the blocks, and the minimum time
+/// between two status lines.
+fn output_settings(config: &config::Config) -> (Duration, Duration) {
+    let section = config.section("output");
+    let coalesce_window = section
+        .duration_or("coalesce_window", Some(DEFAULT_COALESCE_WINDOW))
+        .unwrap_or_default();
+    let min_interval = section
+        .duration_or("min_interval", Some(DEFAULT_MIN_INTERVAL))
+        .unwrap_or_default();
+    (coalesce_window, min_interval)
+}
+
+/// Returns the real-time signals that update modules, with the indices of these modules.
+fn module_signals(config: &config::Config, modules: &[Supervisor]) -> Vec<(libc::c_int, usize)> {
+    modules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, module)| Some((refresh_signal(config.section(module.section()))?, i)))
+        .collect()
+}
+
+/// Returns all of the signals to receive.
+fn handled_signals(refresh_signals: &[(libc::c_int, usize)]) -> Vec<libc::c_int> {
+    let mut signals = vec![STOP_SIGNAL, CONT_SIGNAL];
+    signals.extend(refresh_signals.iter().map(|(signal, _)| *signal));
+    signals
+}
+
+/// Runs a command received on the control socket, except `reload` which is handled by the
+/// caller, and returns the reply. Modules that must be updated are marked in `to_update`.
+fn run_command(
+    command: control::Command,
+    modules: &mut [Supervisor],
+    to_update: &mut [bool],
+    dirty: &mut bool,
+) -> Result<String, String> {
+    let find = |name: &str| -> Result<Vec<usize>, String> {
+        let indices: Vec<usize> = (0..modules.len())
+            .filter(|i| modules[*i].section() == name)
+            .collect();
+        if indices.is_empty() {
+            return Err(format!("unknown module: {}", name));
+        }
+        Ok(indices)
+    };
+    match command {
+        control::Command::Refresh(None) => to_update.fill(true),
+        control::Command::Refresh(Some(name)) => {
+            for i in find(&name)? {
+                to_update[i] = true;
+            }
+        }
+        control::Command::Set { module, key, value } => {
+            for i in find(&module)? {
+                *dirty |= modules[i]
+                    .set(&key, &value)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        control::Command::Dump => {
+            let mut reply = String::new();
+            for module in modules.iter() {
+                for block in module.render() {
+                    reply.push_str(&format!("{}: {}\n", module.section(), block.text));
+                }
+            }
+            return Ok(reply);
+        }
+        control::Command::Reload => {}
+    }
+    Ok(String::new())
+}
+
+/// Sends a command to the running instance and prints the reply. Returns the exit code.
+fn ctl(args: &[String]) -> i32 {
+    let path = match control::socket_path() {
+        Some(p) => p,
+        None => {
+            eprintln!("XDG_RUNTIME_DIR is not set");
+            return 1;
+        }
+    };
+    match control::send(&path, &args.join(" ")) {
+        Ok(reply) => {
+            if let Some(err) = reply.strip_prefix("error: ") {
+                eprint!("{}", err);
+                1
+            } else {
+                print!("{}", reply);
+                0
+            }
+        }
+        Err(err) => {
+            eprintln!("failed to send the command to {}: {}", path.display(), err);
+            1
+        }
+    }
+}
+
+/// Forwards a click event to the module that rendered the clicked block. Returns `true` if the
+/// blocks need to be rerendered.
+fn dispatch_click(modules: &mut [Supervisor], click: &click::Click) -> bool {
+    let module = click
+        .name
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| modules.get_mut(i));
+    match module {
+        Some(module) => module.click(&ClickEvent {
+            button: click.button,
+            block: click.instance.parse().unwrap_or(0),
+        }),
+        None => false,
+    }
+}
+
+/// Renders all modules into one element of the infinite array of status lines.
+fn render_line(modules: &[Supervisor]) -> String {
+    let mut line = String::from("[");
+    let mut first_block = true;
+    for (i, module) in modules.iter().enumerate() {
+        for (j, block) in module.render().enumerate() {
+            if first_block {
+                first_block = false;
+            } else {
+                line.push(',');
+            }
+            // The name and the instance are sent back by i3bar in click events.
+            line.push_str(&format!(
+                "{{\"name\":\"{}\",\"instance\":\"{}\",\"full_text\":\"{}\"",
+                i,
+                j,
+                escape_json_string(&block.text)
+            ));
+            if let Some(short_text) = &block.short_text {
+                line.push_str(&format!(
+                    ",\"short_text\":\"{}\"",
+                    escape_json_string(short_text)
+                ));
+            }
+            let color = if block.is_warning {
+                Some("#ff0000")
+            } else if let Some(color) = &block.color {
+                Some(color.as_str())
+            } else if block.is_dimmed {
+                Some("#808080")
+            } else {
+                None
+            };
+            if let Some(color) = color {
+                line.push_str(&format!(",\"color\":\"{}\"", escape_json_string(color)));
+            }
+            if block.is_urgent {
+                line.push_str(",\"urgent\":true");
+            }
+            line.push('}');
+        }
+    }
+    line.push_str("],");
+    line
+}
+
+/// Returns the real-time signal in the `signal` option of a module's section. `signal = 1` means
+/// `SIGRTMIN+1`.
+fn refresh_signal(section: &config::Section) -> Option<libc::c_int> {
+    let value = section.get("signal")?;
+    let signal = value
+        .parse::<libc::c_int>()
+        .ok()
+        .filter(|n| *n >= 0)
+        .and_then(|n| libc::SIGRTMIN().checked_add(n))
+        .filter(|s| *s <= libc::SIGRTMAX());
+    if signal.is_none() {
+        eprintln!("invalid signal in [{}]: {}", section.name, value);
+    }
+    signal
+}
+
+/// Returns when a module must be updated. While the bar is hidden, only the expiries are kept, as
+/// the other timers only refresh the blocks.
+fn module_deadline(module: &dyn Module, is_hidden: bool) -> Option<Instant> {
+    let expiry = module.expiry();
+    if is_hidden {
+        return expiry;
+    }
+    match (module.timeout(), expiry) {
+        (Some(timeout), Some(expiry)) => Some(timeout.min(expiry)),
+        (timeout, expiry) => timeout.or(expiry),
+    }
+}
+
+/// Makes the reactor follow the file descriptor and the deadline of a module, which any call to
+/// the module can change, so it is done after the module was called. Also subscribes to the
+/// kernel events of a module that was just created. The deadlines are suspended while the bar is
+/// hidden, except for the expiries. The file descriptors are still polled, so a module that
+/// wakes up with its own timerfd, such as a clock, keeps being updated.
+fn watch_module(
+    reactor: &mut Reactor,
+    uevents: Option<&mut uevent::Hub>,
+    i: usize,
+    module: &mut Supervisor,
+    is_hidden: bool,
+) {
+    let token = Source::Module(i).token();
+    if let Err(err) = reactor.set_fd(token, module.pollable_fd()) {
+        eprintln!("failed to poll module {}: {:?}", i, err);
+    }
+    reactor.set_deadline(token, module_deadline(module, is_hidden));
+    if !module.take_started() {
+        return;
+    }
+    if let Some(hub) = uevents {
+        hub.unsubscribe(i);
+        for subscription in module.uevent_subscriptions() {
+            hub.subscribe(i, subscription);
+        }
+    }
+}
+
+/// Opens the socket that receives the kernel events once for all of the modules, polls it and
+/// subscribes the modules.
+fn open_uevents(reactor: &mut Reactor, modules: &[Supervisor]) -> Option<uevent::Hub> {
+    let mut hub = match uevent::Hub::new(uevent::Group::udev_if_running()) {
+        Ok(hub) => hub,
+        Err(err) => {
+            eprintln!("failed to open the uevent socket: {:?}", err);
+            return None;
+        }
+    };
+    for (i, module) in modules.iter().enumerate() {
+        for subscription in module.uevent_subscriptions() {
+            hub.subscribe(i, subscription);
+        }
+    }
+    if let Err(err) = reactor.register(hub.as_raw_fd(), Source::Uevents.token()) {
+        eprintln!("failed to poll the uevent socket: {:?}", err);
+    }
+    Some(hub)
 }
 
 fn main() {
+    // The client of the control socket is the same binary, called either as `gstatus-ctl` or
+    // with `ctl` as the first argument.
+    let args: Vec<String> = env::args().collect();
+    let program = args.first().map(Path::new).and_then(Path::file_name);
+    if program == Some(OsStr::new("gstatus-ctl")) {
+        process::exit(ctl(&args[1..]));
+    }
+    if args.get(1).map(String::as_str) == Some("ctl") {
+        process::exit(ctl(&args[2..]));
+    }
+
+    let mut config = config::Config::load();
+    let mut modules = create_modules(&config);
+
+    // Scripts can send a real-time signal to update some modules right away.
+    let mut refresh_signals = module_signals(&config, &modules);
+
+    // i3bar sends the stop and cont signals when the bar is hidden and shown again. All of the
+    // signals must be blocked before the header is printed, as their default action is to
+    // terminate the process.
+    let signals = match signal::SignalFd::open(&handled_signals(&refresh_signals)) {
+        Ok(val) => Some(val),
+        Err(err) => {
+            eprintln!("failed to open signalfd: {:?}", err);
+            None
+        }
+    };
+
     // i3 protocol start.
-    print!("{{\"version\":1}}\n[");
+    if signals.is_some() {
+        print!(
+            "{{\"version\":1,\"click_events\":true,\"stop_signal\":{},\"cont_signal\":{}}}\n[",
+            STOP_SIGNAL, CONT_SIGNAL
+        );
+    } else {
+        // i3bar sends SIGSTOP and SIGCONT by default.
+        print!("{{\"version\":1,\"click_events\":true}}\n[");
+    }
+
+    let mut clicks = click::Reader::new();
 
-    // Create modules.
-    let mut modules: Vec<Box<dyn Module>> = Vec::new();
-    match Mem::open() {
-        Ok(val) => modules.push(Box::new(val)),
-        Err(err) => eprintln!("failed to open meminfo: {:?}", err),
+    // Register the file descriptors and the deadlines once.
+    let mut reactor = match Reactor::new() {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("failed to create the reactor: {:?}", err);
+            process::exit(1);
+        }
+    };
+    for (i, module) in modules.iter_mut().enumerate() {
+        module.take_touched();
+        watch_module(&mut reactor, None, i, module, false);
+    }
+    let mut uevents = open_uevents(&mut reactor, &modules);
+    if let Err(err) = reactor.register(click::Reader::FD, Source::Clicks.token()) {
+        eprintln!("failed to poll click events: {:?}", err);
+    }
+    if let Some(signals) = &signals {
+        if let Err(err) = reactor.register(signals.as_raw_fd(), Source::Signals.token()) {
+            eprintln!("failed to poll signals: {:?}", err);
+        }
+    }
+
+    // Scripts and keybindings can send commands.
+    let control = control::socket_path().and_then(|path| match control::Server::bind(&path) {
+        Ok(val) => Some(val),
+        Err(err) => {
+            eprintln!("failed to bind {}: {:?}", path.display(), err);
+            None
+        }
+    });
+    if let Some(control) = &control {
+        if let Err(err) = reactor.register(control.as_raw_fd(), Source::Control.token()) {
+            eprintln!("failed to poll the control socket: {:?}", err);
+        }
+    }
+    let mut connections: HashMap<u32, control::Connection> = HashMap::new();
+
+    // The modules that show the time must know when it jumps.
+    let mut clock_changes = match timechange::ClockWatcher::open() {
+        Ok(val) => Some(val),
+        Err(err) => {
+            eprintln!("failed to watch the system clock: {:?}", err);
+            None
+        }
     };
-    match Batteries::new() {
-        Ok(val) => modules.push(Box::new(val)),
-        Err(err) => eprintln!("failed to create the batteries module: {:?}", err),
+    if let Some(clock_changes) = &clock_changes {
+        let token = Source::ClockChanges.token();
+        if let Err(err) = reactor.register(clock_changes.as_raw_fd(), token) {
+            eprintln!("failed to poll the timerfd: {:?}", err);
+        }
+    }
+    let zone_changes = match timechange::ZoneWatcher::open() {
+        Ok(val) => Some(val),
+        Err(err) => {
+            eprintln!("failed to watch the local time zone: {:?}", err);
+            None
+        }
     };
-    modules.push(Box::new(Clock::new()));
+    if let Some(zone_changes) = &zone_changes {
+        let token = Source::ZoneChanges.token();
+        if let Err(err) = reactor.register(zone_changes.as_raw_fd(), token) {
+            eprintln!("failed to poll inotify: {:?}", err);
+        }
+    }
+    let mut next_connection_id: u32 = 0;
+
+    let (mut coalesce_window, mut min_interval) = output_settings(&config);
+    let mut last_line = String::new();
+    let mut last_print: Option<Instant> = None;
+    // Print the blocks right away at startup.
+    let mut render_at = Some(Instant::now());
+    reactor.set_deadline(Source::Render.token(), render_at);
+    // While the bar is hidden, the deadlines are suspended except for the expiries, and nothing
+    // is printed.
+    let mut is_hidden = false;
 
     loop {
-        // Render all modules.
-        print!("[");
-        let mut first_block = true;
-        for module in modules.iter() {
-            for block in module.render() {
-                if first_block {
-                    first_block = false;
-                } else {
-                    print!(",");
+        let ready = match reactor.wait() {
+            Ok(val) => val,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::Interrupted {
+                    eprintln!("epoll_wait() failed: {:?}", err);
                 }
-                let text = escape_json_string(&block.text);
-                if block.is_warning {
-                    print!("{{\"full_text\":\"{}\",\"color\":\"#ff0000\"}}", text);
-                } else {
-                    print!("{{\"full_text\":\"{}\"}}", text);
+                continue;
+            }
+        };
+
+        // Update each module once even if both its file descriptor and its deadline are
+        // ready.
+        let mut to_update = vec![false; modules.len()];
+        let mut dirty = false;
+        let mut render_now = false;
+        let mut reload = false;
+        let mut time_changed = false;
+        let mut reopen_uevents = false;
+        // The deadlines of every module must be suspended or resumed.
+        let mut hidden_changed = false;
+        for r in ready {
+            match (Source::from_token(r.token), r.kind) {
+                (Source::Render, _) => render_now = true,
+                (Source::Control, _) => loop {
+                    let connection = match control.as_ref().unwrap().accept() {
+                        Ok(Some(val)) => val,
+                        Ok(None) => break,
+                        Err(err) => {
+                            eprintln!("failed to accept a control connection: {:?}", err);
+                            break;
+                        }
+                    };
+                    let id = next_connection_id;
+                    next_connection_id = next_connection_id.wrapping_add(1);
+                    let token = Source::Connection(id).token();
+                    if let Err(err) = reactor.register(connection.as_raw_fd(), token) {
+                        eprintln!("failed to poll a control connection: {:?}", err);
+                        continue;
+                    }
+                    connections.insert(id, connection);
+                },
+                (Source::Connection(id), _) => {
+                    let connection = match connections.get_mut(&id) {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    let reply = match connection.read() {
+                        Ok(Some(line)) => match control::Command::parse(&line) {
+                            Ok(control::Command::Reload) => {
+                                reload = true;
+                                Ok(String::new())
+                            }
+                            Ok(command) => {
+                                run_command(command, &mut modules, &mut to_update, &mut dirty)
+                            }
+                            Err(err) => Err(err.to_string()),
+                        },
+                        // Wait for the rest of the line.
+                        Ok(None) => continue,
+                        Err(err) => {
+                            if err.kind() != io::ErrorKind::UnexpectedEof {
+                                eprintln!("failed to read a control command: {:?}", err);
+                            }
+                            Err(err.to_string())
+                        }
+                    };
+                    let reply = match reply {
+                        Ok(val) => val,
+                        Err(err) => format!("error: {}\n", err),
+                    };
+                    if let Err(err) = connection.reply(&reply) {
+                        eprintln!("failed to reply to a control command: {:?}", err);
+                    }
+                    if let Err(err) = reactor.deregister(Source::Connection(id).token()) {
+                        eprintln!("failed to stop polling a control connection: {:?}", err);
+                    }
+                    connections.remove(&id);
+                }
+                (Source::Signals, _) => {
+                    let received = match signals.as_ref().unwrap().read() {
+                        Ok(val) => val,
+                        Err(err) => {
+                            eprintln!("failed to read signals: {:?}", err);
+                            continue;
+                        }
+                    };
+                    for signal in received {
+                        if signal == STOP_SIGNAL && !is_hidden {
+                            is_hidden = true;
+                            hidden_changed = true;
+                            render_at = None;
+                            reactor.set_deadline(Source::Render.token(), None);
+                        } else if signal == CONT_SIGNAL && is_hidden {
+                            is_hidden = false;
+                            hidden_changed = true;
+                            // Refresh everything as the timers didn't fire.
+                            to_update.fill(true);
+                            render_now = true;
+                        }
+                        for (_, i) in refresh_signals.iter().filter(|(s, _)| *s == signal) {
+                            to_update[*i] = true;
+                        }
+                    }
+                }
+                (Source::ClockChanges, _) => match clock_changes.as_mut().unwrap().read() {
+                    Ok(changed) => time_changed |= changed,
+                    Err(err) => eprintln!("failed to read the timerfd: {:?}", err),
+                },
+                (Source::ZoneChanges, _) => match zone_changes.as_ref().unwrap().read() {
+                    Ok(changed) => time_changed |= changed,
+                    Err(err) => eprintln!("failed to read inotify: {:?}", err),
+                },
+                (Source::Module(i), ReadyKind::Hangup) => {
+                    // The supervisor doesn't fail, it creates the module again.
+                    dirty |= modules[i].hangup().unwrap_or(true);
+                }
+                (Source::Module(i), _) => to_update[i] = true,
+                (Source::Uevents, ReadyKind::Hangup) => reopen_uevents = true,
+                (Source::Uevents, _) => {
+                    let hub = uevents.as_mut().unwrap();
+                    dirty |= hub.dispatch(|j, notification| match notification {
+                        uevent::Notification::Event(event) => modules[j].uevent(event),
+                        uevent::Notification::Overflow => modules[j].uevent_overflow(),
+                    });
                 }
+                (Source::Clicks, kind) => loop {
+                    match clicks.read() {
+                        Ok(events) => {
+                            for event in events {
+                                dirty |= dispatch_click(&mut modules, &event);
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("failed to read click events: {:?}", err);
+                            break;
+                        }
+                    }
+                    if clicks.is_eof() {
+                        if let Err(err) = reactor.deregister(Source::Clicks.token()) {
+                            eprintln!("failed to stop polling click events: {:?}", err);
+                        }
+                        break;
+                    }
+                    // After a hang up, the remaining data must be read now as standard input
+                    // won't be polled anymore.
+                    if kind == ReadyKind::Readable {
+                        break;
+                    }
+                },
             }
         }
-        print!("],");
-
-        // Flush standard output as we don't have a LF and we're going to sleep now.
-        if let Err(err) = io::stdout().flush() {
-            eprintln!("failed to flush stdout: {:?}", err);
-        }
-
-        // Now, wait until something changes...
-        'dirty: loop {
-            // Find the first module to timeout.
-            let now = Instant::now();
-            let mut min_diff_ms = -1;
-            let mut min_diff_i = 0;
-            for (i, module) in modules.iter_mut().enumerate() {
-                let timeout = match module.timeout() {
-                    Some(val) => val,
-                    None => continue,
-                };
-                let diff_ms = timeout
-                    .checked_duration_since(now)
-                    .map(|diff| libc::c_int::try_from(diff.as_millis()).unwrap_or(libc::c_int::MAX))
-                    .unwrap_or(0);
-                if diff_ms == 0 {
-                    if module.update() {
-                        break 'dirty;
-                    }
-                } else if min_diff_ms == -1 || diff_ms < min_diff_ms {
-                    min_diff_ms = diff_ms;
-                    min_diff_i = i;
+        if reload {
+            config = config::Config::load();
+            for i in 0..modules.len() {
+                let token = Source::Module(i).token();
+                if let Err(err) = reactor.deregister(token) {
+                    eprintln!("failed to stop polling module {}: {:?}", i, err);
+                }
+                reactor.set_deadline(token, None);
+                if let Some(hub) = uevents.as_mut() {
+                    hub.unsubscribe(i);
                 }
             }
-
-            // Collect module pollable FDs.
-            let mut fds = Vec::new();
-            let mut indices = Vec::new();
-            for (i, module) in modules.iter().enumerate() {
-                let fd = match module.pollable_fd() {
-                    Some(val) => val,
-                    None => continue,
-                };
-                fds.push(libc::pollfd {
-                    fd,
-                    events: libc::EPOLLIN as i16,
-                    revents: 0,
-                });
-                indices.push(i);
-            }
-            let fds_ptr = fds.as_mut_ptr();
-
-            let ret = unsafe { libc::poll(fds_ptr, fds.len().try_into().unwrap(), min_diff_ms) };
-            if ret == -1 {
-                eprintln!("poll() failed: {:?}", io::Error::last_os_error());
-            } else if ret == 0 {
-                if modules[min_diff_i].update() {
-                    break 'dirty;
+            // Drop the old modules first so that they release their devices.
+            modules.clear();
+            modules = create_modules(&config);
+            to_update = vec![false; modules.len()];
+            refresh_signals = module_signals(&config, &modules);
+            if let Some(signals) = &signals {
+                if let Err(err) = signals.set_signals(&handled_signals(&refresh_signals)) {
+                    eprintln!("failed to change the signals: {:?}", err);
                 }
-            } else {
-                let mut dirty = false;
-                for (i, fd) in fds.iter().enumerate() {
-                    if i32::from(fd.revents) == libc::EPOLLIN {
-                        let module = &mut modules[indices[i]];
-                        dirty |= module.update();
-                    }
+            }
+            (coalesce_window, min_interval) = output_settings(&config);
+            dirty = true;
+        }
+        if reopen_uevents {
+            eprintln!("the uevent socket failed, opening it again");
+            if let Err(err) = reactor.deregister(Source::Uevents.token()) {
+                eprintln!("failed to stop polling the uevent socket: {:?}", err);
+            }
+            uevents = open_uevents(&mut reactor, &modules);
+            // Events may have been lost in the meantime.
+            for module in modules.iter_mut() {
+                if !module.uevent_subscriptions().is_empty() {
+                    dirty |= module.uevent_overflow();
                 }
-                if dirty {
-                    break 'dirty;
+            }
+        }
+        for (i, module) in modules.iter_mut().enumerate() {
+            if time_changed {
+                dirty |= module.time_changed();
+            }
+            if to_update[i] {
+                dirty |= module.update();
+            }
+            if module.take_touched() || hidden_changed {
+                watch_module(&mut reactor, uevents.as_mut(), i, module, is_hidden);
+            }
+        }
+
+        // The blocks are printed when the bar is shown again.
+        if is_hidden {
+            continue;
+        }
+        if render_now {
+            render_at = None;
+            let line = render_line(&modules);
+            // i3bar would redraw the bar for nothing.
+            if line != last_line {
+                print!("{}", line);
+                // Flush standard output as we don't have a LF and we're going to sleep now.
+                if let Err(err) = io::stdout().flush() {
+                    eprintln!("failed to flush stdout: {:?}", err);
                 }
+                last_print = Some(Instant::now());
+                last_line = line;
+            }
+        } else if dirty && render_at.is_none() {
+            // Wait a bit in case more updates follow, for example during a burst of kernel events,
+            // and don't print more often than allowed.
+            let mut at = Instant::now() + coalesce_window;
+            if let Some(last_print) = last_print {
+                at = at.max(last_print + min_interval);
             }
+            render_at = Some(at);
+            reactor.set_deadline(Source::Render.token(), render_at);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn timer_expires_while_hidden() {
+        let config = config::Config::parse("[timer:tea]\nduration = 50ms\n").unwrap();
+        let section = config.section("timer:tea").clone();
+        let mut timer = Supervisor::new("timer:tea", "tea", move || {
+            Ok(Box::new(Timer::new("tea", &section)))
+        });
+        assert_eq!(module_deadline(&timer, true), None);
+
+        timer.click(&ClickEvent {
+            button: 1,
+            block: 0,
+        });
+        let at = module_deadline(&timer, true).unwrap();
+        assert!(at <= Instant::now() + Duration::from_millis(50));
+        assert!(module_deadline(&timer, false).is_some());
+
+        thread::sleep(at.saturating_duration_since(Instant::now()));
+        assert!(timer.update());
+        assert!(timer.render().next().unwrap().is_urgent);
+        assert_eq!(module_deadline(&timer, true), None);
+    }
+}
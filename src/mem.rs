@@ -86,6 +86,7 @@ impl Module for Mem {
         let block = Block {
             text: format!("Mem: {}%", self.percentage),
             is_warning: self.percentage >= 70,
+            ..Default::default()
         };
         Box::new(iter::once(block))
     }
@@ -1,12 +1,78 @@
 use std::os::unix::io::RawFd;
 use std::time::Instant;
+use std::{error, fmt, io};
 
+use crate::uevent;
+
+#[derive(Clone, Default, PartialEq)]
 pub(crate) struct Block {
     /// The text in the block.
     pub text: String,
 
+    /// A shorter text that i3bar uses when there is not enough space.
+    pub short_text: Option<String>,
+
+    /// The color of the text, such as `#00ff00`, unless the block is a warning.
+    pub color: Option<String>,
+
     /// This will display the block in red.
     pub is_warning: bool,
+
+    /// This will display the block in gray, for example when the module is not working.
+    pub is_dimmed: bool,
+
+    /// This will make i3bar highlight the block.
+    pub is_urgent: bool,
+}
+
+/// A click on one of the blocks of a `Module`.
+pub(crate) struct ClickEvent {
+    /// The mouse button: 1 to 3 for the left, middle and right buttons, 4 and 5 for scrolling up
+    /// and down.
+    pub button: u32,
+
+    /// The index of the clicked block among the blocks of the module.
+    pub block: usize,
+}
+
+/// The error for an option that a module doesn't have.
+pub(crate) fn unknown_option(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unknown option: {}", key),
+    )
+}
+
+/// The error for an invalid value of an option.
+pub(crate) fn invalid_value(key: &str, value: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid value for {}: {}", key, value),
+    )
+}
+
+/// The error of a module whose device doesn't exist. See `no_device`.
+#[derive(Debug)]
+struct NoDevice(String);
+
+impl fmt::Display for NoDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for NoDevice {}
+
+/// The error for hardware that this computer doesn't have, such as the backlight of a desktop
+/// computer. Such a module is hidden instead of being shown as unavailable, until the device
+/// appears.
+pub(crate) fn no_device(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, NoDevice(message.to_owned()))
+}
+
+/// Returns `true` if the error was created by `no_device`.
+pub(crate) fn is_no_device(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<NoDevice>())
 }
 
 pub(crate) trait Module {
@@ -23,7 +89,62 @@ pub(crate) trait Module {
     /// called.
     fn pollable_fd(&self) -> Option<RawFd>;
 
+    /// This method is called when the file descriptor returned by `pollable_fd` reports a hang up
+    /// or an error. It is not polled anymore afterwards, unless `pollable_fd` returns another one.
+    /// It returns `true` if the blocks need to be rerendered, or an error if the module doesn't
+    /// work anymore and must be created again.
+    fn hangup(&mut self) -> io::Result<bool> {
+        Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "the file descriptor hung up",
+        ))
+    }
+
     /// If this method returns some instant, then the module should be updated
     /// before that instant.
     fn timeout(&self) -> Option<Instant>;
+
+    /// If this method returns some instant, then the module must be updated at that instant even
+    /// while the bar is hidden, for example because a countdown expires. Unlike `timeout`, it
+    /// isn't only about keeping the blocks up to date.
+    fn expiry(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Returns the kernel events that the module wants to receive in `uevent`.
+    fn uevent_subscriptions(&self) -> Vec<uevent::Subscription> {
+        Vec::new()
+    }
+
+    /// This method is called for each kernel event that matches one of the module's
+    /// subscriptions. It returns `true` if the blocks need to be rerendered.
+    fn uevent(&mut self, _event: &uevent::Event) -> bool {
+        false
+    }
+
+    /// This method is called when kernel events were lost, in which case the module should read
+    /// the state of the devices it follows again. It returns `true` if the blocks need to be
+    /// rerendered.
+    fn uevent_overflow(&mut self) -> bool {
+        false
+    }
+
+    /// This method is called when the system clock is set, when the local time zone changes, and
+    /// when the system resumes from suspend, in which case the time that the module shows may be
+    /// wrong. It returns `true` if the blocks need to be rerendered.
+    fn time_changed(&mut self) -> bool {
+        false
+    }
+
+    /// This method is called when one of the blocks is clicked. It returns `true` if the blocks
+    /// need to be rerendered.
+    fn click(&mut self, _event: &ClickEvent) -> bool {
+        false
+    }
+
+    /// This method is called for the `set` command of the control socket. It returns `true` if
+    /// the blocks need to be rerendered.
+    fn set(&mut self, key: &str, _value: &str) -> io::Result<bool> {
+        Err(unknown_option(key))
+    }
 }
@@ -0,0 +1,395 @@
+use std::convert::{TryFrom, TryInto};
+use std::{io, mem, os::unix::io::AsRawFd, os::unix::io::RawFd};
+
+const NLMSG_HDRLEN: usize = mem::size_of::<libc::nlmsghdr>();
+const NLA_HDRLEN: usize = mem::size_of::<libc::nlattr>();
+const GENL_HDRLEN: usize = mem::size_of::<libc::genlmsghdr>();
+
+/// Big enough for the largest dump messages sent by the kernel.
+const RECV_BUF_SIZE: usize = 32 * 1024;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A netlink attribute.
+pub(crate) struct Attr<'a> {
+    pub kind: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Attr<'a> {
+    pub(crate) fn u8(&self) -> Option<u8> {
+        self.payload.first().copied()
+    }
+
+    pub(crate) fn u16(&self) -> Option<u16> {
+        Some(u16::from_ne_bytes(self.payload.get(..2)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32(&self) -> Option<u32> {
+        Some(u32::from_ne_bytes(self.payload.get(..4)?.try_into().ok()?))
+    }
+
+    /// Returns the payload as a string, without the trailing NUL byte if there is one.
+    pub(crate) fn str(&self) -> Option<&'a str> {
+        let bytes = match self.payload.iter().position(|b| *b == b'\0') {
+            Some(end) => &self.payload[..end],
+            None => self.payload,
+        };
+        std::str::from_utf8(bytes).ok()
+    }
+
+    pub(crate) fn nested(&self) -> Attrs<'a> {
+        Attrs::new(self.payload)
+    }
+}
+
+/// An iterator over the netlink attributes in a buffer. Iteration stops at the first malformed
+/// attribute.
+pub(crate) struct Attrs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Attrs<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for Attrs<'a> {
+    type Item = Attr<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = usize::from(u16::from_ne_bytes([self.buf[0], self.buf[1]]));
+        if len < NLA_HDRLEN || len > self.buf.len() {
+            self.buf = &[];
+            return None;
+        }
+        let kind = u16::from_ne_bytes([self.buf[2], self.buf[3]]) & (libc::NLA_TYPE_MASK as u16);
+        let payload = &self.buf[NLA_HDRLEN..len];
+        self.buf = &self.buf[align(len).min(self.buf.len())..];
+        Some(Attr { kind, payload })
+    }
+}
+
+/// A netlink message received from the kernel.
+pub(crate) struct Message {
+    pub kind: u16,
+    pub flags: u16,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    /// Splits a generic netlink message payload into its command and attributes.
+    pub(crate) fn genl(&self) -> Option<(u8, Attrs<'_>)> {
+        let cmd = *self.payload.first()?;
+        let attrs = self.payload.get(GENL_HDRLEN..)?;
+        Some((cmd, Attrs::new(attrs)))
+    }
+}
+
+/// Parses all of the netlink messages contained in a datagram.
+pub(crate) fn parse_messages(mut buf: &[u8]) -> io::Result<Vec<Message>> {
+    let mut r = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = usize::try_from(u32::from_ne_bytes(buf[0..4].try_into().unwrap())).unwrap();
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return Err(invalid_data("invalid netlink message length"));
+        }
+        r.push(Message {
+            kind: u16::from_ne_bytes(buf[4..6].try_into().unwrap()),
+            flags: u16::from_ne_bytes(buf[6..8].try_into().unwrap()),
+            seq: u32::from_ne_bytes(buf[8..12].try_into().unwrap()),
+            payload: buf[NLMSG_HDRLEN..len].to_vec(),
+        });
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    Ok(r)
+}
+
+/// Builds a netlink message to send to the kernel.
+pub(crate) struct MessageBuilder {
+    buf: Vec<u8>,
+}
+
+impl MessageBuilder {
+    pub(crate) fn new(kind: u16, flags: u16) -> Self {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&kind.to_ne_bytes());
+        buf[6..8].copy_from_slice(&(flags | libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        Self { buf }
+    }
+
+    /// Creates a generic netlink message for the given family and command.
+    pub(crate) fn genl(family: u16, cmd: u8, flags: u16) -> Self {
+        let mut r = Self::new(family, flags);
+        r.push(&[cmd, 1, 0, 0]);
+        r
+    }
+
+    /// Appends raw data to the message, such as a protocol specific header.
+    pub(crate) fn push(&mut self, data: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+        self
+    }
+
+    pub(crate) fn push_attr(&mut self, kind: u16, payload: &[u8]) -> &mut Self {
+        let len = u16::try_from(NLA_HDRLEN + payload.len()).unwrap();
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        self.push(payload)
+    }
+
+    fn finish(mut self, seq: u32) -> Vec<u8> {
+        let len = u32::try_from(self.buf.len()).unwrap();
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buf
+    }
+}
+
+pub(crate) struct Socket {
+    fd: libc::c_int,
+    seq: u32,
+}
+
+impl Socket {
+    /// Tries to open and bind a netlink socket for the given protocol. An error is returned if the
+    /// socket could not be opened.
+    pub(crate) fn open(
+        protocol: libc::c_int,
+        non_blocking: bool,
+        close_on_exec: bool,
+    ) -> io::Result<Self> {
+        let mut flags = libc::SOCK_RAW;
+        if non_blocking {
+            flags |= libc::SOCK_NONBLOCK;
+        }
+        if close_on_exec {
+            flags |= libc::SOCK_CLOEXEC;
+        }
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, flags, protocol) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Construct the socket as early as possible to get RAII to automatically close it if there
+        // is an error.
+        let socket = Self { fd, seq: 0 };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of_val(&addr) as libc::c_uint,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    /// Subscribes to a multicast group.
+    pub(crate) fn add_membership(&self, group: u32) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_NETLINK,
+                libc::NETLINK_ADD_MEMBERSHIP,
+                &group as *const u32 as *const libc::c_void,
+                mem::size_of_val(&group) as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, msg: MessageBuilder) -> io::Result<u32> {
+        self.seq = self.seq.wrapping_add(1);
+        let buf = msg.finish(self.seq);
+        let ret = unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(self.seq)
+    }
+
+    /// Receives the messages contained in the next datagram.
+    pub(crate) fn recv(&self) -> io::Result<Vec<Message>> {
+        let mut buf = vec![0u8; RECV_BUF_SIZE];
+        let ret = unsafe {
+            libc::recv(
+                self.fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_TRUNC,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let read = usize::try_from(ret).unwrap();
+        if read > buf.len() {
+            return Err(invalid_data("netlink datagram is too big"));
+        }
+        parse_messages(&buf[..read])
+    }
+
+    /// Sends a request and collects the replies. The socket must be blocking.
+    pub(crate) fn request(&mut self, msg: MessageBuilder) -> io::Result<Vec<Message>> {
+        let seq = self.send(msg)?;
+        let mut replies = Vec::new();
+        loop {
+            for msg in self.recv()? {
+                if msg.seq != seq {
+                    continue;
+                }
+                match libc::c_int::from(msg.kind) {
+                    libc::NLMSG_DONE => return Ok(replies),
+                    libc::NLMSG_ERROR => {
+                        let errno = msg
+                            .payload
+                            .get(..4)
+                            .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+                            .ok_or_else(|| invalid_data("truncated netlink error message"))?;
+                        if errno == 0 {
+                            return Ok(replies);
+                        }
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                    _ => {
+                        let last = (libc::c_int::from(msg.flags) & libc::NLM_F_MULTI) == 0;
+                        replies.push(msg);
+                        if last {
+                            return Ok(replies);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::close(self.fd) };
+        if ret == -1 {
+            eprintln!(
+                "failed to close netlink socket: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// A generic netlink family, as resolved by the controller.
+pub(crate) struct Family {
+    pub id: u16,
+    pub mcast_groups: Vec<(String, u32)>,
+}
+
+impl Family {
+    pub(crate) fn resolve(socket: &mut Socket, name: &str) -> io::Result<Self> {
+        let mut name_z = name.as_bytes().to_vec();
+        name_z.push(b'\0');
+        let mut msg =
+            MessageBuilder::genl(libc::GENL_ID_CTRL as u16, libc::CTRL_CMD_GETFAMILY as u8, 0);
+        msg.push_attr(libc::CTRL_ATTR_FAMILY_NAME as u16, &name_z);
+
+        let replies = socket.request(msg)?;
+        let (_, attrs) = replies
+            .first()
+            .and_then(|m| m.genl())
+            .ok_or_else(|| invalid_data("missing generic netlink family reply"))?;
+        let mut id = None;
+        let mut mcast_groups = Vec::new();
+        for attr in attrs {
+            match libc::c_int::from(attr.kind) {
+                libc::CTRL_ATTR_FAMILY_ID => id = attr.u16(),
+                libc::CTRL_ATTR_MCAST_GROUPS => {
+                    for group in attr.nested() {
+                        let mut group_name = None;
+                        let mut group_id = None;
+                        for a in group.nested() {
+                            match libc::c_int::from(a.kind) {
+                                libc::CTRL_ATTR_MCAST_GRP_NAME => group_name = a.str(),
+                                libc::CTRL_ATTR_MCAST_GRP_ID => group_id = a.u32(),
+                                _ => {}
+                            }
+                        }
+                        if let (Some(n), Some(i)) = (group_name, group_id) {
+                            mcast_groups.push((n.to_owned(), i));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let id = id.ok_or_else(|| invalid_data("missing generic netlink family ID"))?;
+        Ok(Self { id, mcast_groups })
+    }
+
+    pub(crate) fn mcast_group(&self, name: &str) -> Option<u32> {
+        self.mcast_groups
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_truncated_message() {
+        let mut msg = MessageBuilder::new(libc::NLMSG_DONE as u16, 0);
+        msg.push(&0i32.to_ne_bytes());
+        let buf = msg.finish(1);
+        assert_eq!(parse_messages(&buf).unwrap().len(), 1);
+        let err = parse_messages(&buf[..buf.len() - 4]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stop_at_malformed_attribute() {
+        let mut msg = MessageBuilder::new(0, 0);
+        msg.push_attr(1, &7u32.to_ne_bytes());
+        // The length of the second attribute goes past the end of the buffer.
+        msg.push(&[0xff, 0x00, 0x02, 0x00]);
+        let buf = msg.finish(1);
+        let attrs: Vec<_> = Attrs::new(&buf[NLMSG_HDRLEN..]).collect();
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(attrs[0].u32(), Some(7));
+    }
+
+    #[test]
+    fn mask_attribute_flags() {
+        let mut msg = MessageBuilder::new(0, 0);
+        msg.push_attr(libc::NLA_F_NESTED as u16 | 21, &[]);
+        let buf = msg.finish(1);
+        let attr = Attrs::new(&buf[NLMSG_HDRLEN..]).next().unwrap();
+        assert_eq!(attr.kind, 21);
+    }
+}
@@ -0,0 +1,435 @@
+use std::fmt::Write;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io, iter};
+
+use crate::command::spawn_detached;
+use crate::config;
+use crate::module::{self, Block, ClickEvent, Module};
+use crate::timer::{format_secs, Countdown};
+
+/// Returns `$XDG_STATE_HOME/gstatus/pomodoro`, or `~/.local/state/gstatus/pomodoro`.
+fn state_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(dir.join("gstatus").join("pomodoro"))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+impl Phase {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "work" => Some(Phase::Work),
+            "short_break" => Some(Phase::ShortBreak),
+            "long_break" => Some(Phase::LongBreak),
+            _ => None,
+        }
+    }
+
+    /// Returns the name used in the state file and in the `set` command.
+    fn key(self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::ShortBreak => "short_break",
+            Phase::LongBreak => "long_break",
+        }
+    }
+}
+
+/// Alternates work periods with short breaks, and with a long break after a number of work
+/// periods. The block is urgent when a phase ends, and the next phase starts with the left
+/// button. The state is saved so that it survives restarts.
+pub(crate) struct Pomodoro {
+    work: Duration,
+    short_break: Duration,
+    long_break: Duration,
+    /// The number of work periods before a long break.
+    cycles: u32,
+    /// A shell command that runs when a phase ends.
+    notify: Option<String>,
+    path: Option<PathBuf>,
+    phase: Phase,
+    /// The number of work periods completed since the last long break.
+    completed: u32,
+    /// The time counted in the phase.
+    countdown: Countdown,
+    is_urgent: bool,
+}
+
+impl Pomodoro {
+    const DEFAULT_WORK: Duration = Duration::from_secs(25 * 60);
+    const DEFAULT_SHORT_BREAK: Duration = Duration::from_secs(5 * 60);
+    const DEFAULT_LONG_BREAK: Duration = Duration::from_secs(15 * 60);
+    const DEFAULT_CYCLES: u32 = 4;
+
+    /// Creates the module of the `[pomodoro]` section, and restores the saved state.
+    pub(crate) fn new(config: &config::Section) -> io::Result<Self> {
+        let duration = |key, default| config.duration_or(key, Some(default)).unwrap_or(default);
+        let cycles = match config.get("cycles") {
+            Some(c) => match c.parse() {
+                Ok(n) if n > 0 => n,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid cycles option",
+                    ))
+                }
+            },
+            None => Pomodoro::DEFAULT_CYCLES,
+        };
+        let work = duration("work", Pomodoro::DEFAULT_WORK);
+        let mut r = Self {
+            work,
+            short_break: duration("short_break", Pomodoro::DEFAULT_SHORT_BREAK),
+            long_break: duration("long_break", Pomodoro::DEFAULT_LONG_BREAK),
+            cycles,
+            notify: config.get("notify").map(str::to_owned),
+            path: state_path(),
+            phase: Phase::Work,
+            completed: 0,
+            countdown: Countdown::new(work),
+            is_urgent: false,
+        };
+        if let Err(err) = r.load() {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("failed to load the pomodoro state: {err}");
+            }
+        }
+        // The phase may have ended while gstatus wasn't running.
+        if r.check_end() {
+            r.save();
+        }
+        Ok(r)
+    }
+
+    /// Reads the state file.
+    fn load(&mut self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let s = fs::read_to_string(path)?;
+        self.restore(&s)
+    }
+
+    /// Restores a state returned by `state`, which has one `key=value` line per field.
+    fn restore(&mut self, s: &str) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid state file");
+        let mut elapsed = Duration::ZERO;
+        // The wall-clock time when the phase was started, if it is running.
+        let mut started = None;
+        for line in s.lines() {
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "phase" => self.phase = Phase::parse(value).ok_or_else(invalid)?,
+                "completed" => self.completed = value.parse().map_err(|_| invalid())?,
+                "elapsed_ms" => {
+                    elapsed = Duration::from_millis(value.parse().map_err(|_| invalid())?)
+                }
+                "started_ms" => {
+                    let ms = value.parse().map_err(|_| invalid())?;
+                    started = Some(UNIX_EPOCH + Duration::from_millis(ms));
+                }
+                "urgent" => self.is_urgent = value == "true",
+                // Ignore the keys of other versions.
+                _ => {}
+            }
+        }
+        // The phase kept running while gstatus wasn't, even if the system was shut down.
+        self.countdown = Countdown::new(self.duration());
+        if let Some(started) = started {
+            elapsed += started.elapsed().unwrap_or_default();
+            self.countdown.start();
+        }
+        self.countdown.set_elapsed(elapsed);
+        Ok(())
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return,
+        };
+        // Replace the file at once so that it is never read half-written.
+        let tmp = path.with_extension("tmp");
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&tmp, self.state()))
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(err) = result {
+            eprintln!(
+                "failed to save the pomodoro state to {}: {err}",
+                path.display()
+            );
+        }
+    }
+
+    /// Returns the contents of the state file.
+    fn state(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "phase={}", self.phase.key());
+        let _ = writeln!(s, "completed={}", self.completed);
+        // The countdown isn't a wall-clock time, so the time counted so far is saved with the
+        // current time.
+        let _ = writeln!(s, "elapsed_ms={}", self.countdown.elapsed().as_millis());
+        if self.countdown.is_running() {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let _ = writeln!(s, "started_ms={}", since_epoch.as_millis());
+        }
+        let _ = writeln!(s, "urgent={}", self.is_urgent);
+        s
+    }
+
+    fn duration(&self) -> Duration {
+        match self.phase {
+            Phase::Work => self.work,
+            Phase::ShortBreak => self.short_break,
+            Phase::LongBreak => self.long_break,
+        }
+    }
+
+    fn start(&mut self) {
+        self.countdown.start();
+        self.is_urgent = false;
+    }
+
+    /// Switches to a phase, which waits to be started.
+    fn switch(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.countdown = Countdown::new(self.duration());
+    }
+
+    /// Moves to the next phase if the current one ended. Returns `true` if it did.
+    fn check_end(&mut self) -> bool {
+        if !self.countdown.has_ended() {
+            return false;
+        }
+        let next = match self.phase {
+            Phase::Work => {
+                self.completed += 1;
+                if self.completed >= self.cycles {
+                    Phase::LongBreak
+                } else {
+                    Phase::ShortBreak
+                }
+            }
+            Phase::ShortBreak => Phase::Work,
+            Phase::LongBreak => {
+                self.completed = 0;
+                Phase::Work
+            }
+        };
+        self.switch(next);
+        self.is_urgent = true;
+        if let Some(notify) = &self.notify {
+            if let Err(err) = spawn_detached(notify) {
+                eprintln!("failed to run the notify command of the pomodoro: {err}");
+            }
+        }
+        true
+    }
+}
+
+impl Module for Pomodoro {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let name = match self.phase {
+            Phase::Work => format!("Work {}/{}", self.completed + 1, self.cycles),
+            Phase::ShortBreak => "Break".to_owned(),
+            Phase::LongBreak => "Long break".to_owned(),
+        };
+        // The next phase starts when one ends, so 00:00 is never shown.
+        let mut text = format!("{} {}", name, format_secs(self.countdown.shown_secs()));
+        if self.countdown.is_paused() {
+            text.push_str(" (paused)");
+        }
+        let block = Block {
+            text,
+            is_urgent: self.is_urgent,
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        if self.check_end() {
+            self.save();
+        }
+        self.countdown.is_running() || self.is_urgent
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        self.countdown.timeout()
+    }
+
+    fn expiry(&self) -> Option<Instant> {
+        self.countdown.expiry()
+    }
+
+    fn time_changed(&mut self) -> bool {
+        // The phase may have ended while the system was suspended.
+        self.update()
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        match event.button {
+            1 if self.countdown.is_running() => self.countdown.pause(),
+            1 => self.start(),
+            3 => {
+                self.switch(Phase::Work);
+                self.completed = 0;
+                self.is_urgent = false;
+            }
+            _ => return false,
+        }
+        self.check_end();
+        self.save();
+        true
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        match key {
+            "running" => match value {
+                "true" => self.start(),
+                "false" => self.countdown.pause(),
+                "toggle" if self.countdown.is_running() => self.countdown.pause(),
+                "toggle" => self.start(),
+                _ => return Err(module::invalid_value(key, value)),
+            },
+            "phase" => {
+                let phase = Phase::parse(value).ok_or_else(|| module::invalid_value(key, value))?;
+                self.switch(phase);
+                self.is_urgent = false;
+            }
+            _ => return Err(module::unknown_option(key)),
+        }
+        self.check_end();
+        self.save();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Duration = Duration::from_secs(60);
+
+    fn pomodoro() -> Pomodoro {
+        Pomodoro {
+            work: 25 * MIN,
+            short_break: 5 * MIN,
+            long_break: 15 * MIN,
+            cycles: 2,
+            notify: None,
+            path: None,
+            phase: Phase::Work,
+            completed: 0,
+            countdown: Countdown::new(25 * MIN),
+            is_urgent: false,
+        }
+    }
+
+    /// Makes the current phase reach its end.
+    fn end_phase(p: &mut Pomodoro) {
+        p.start();
+        p.countdown.set_elapsed(p.duration());
+    }
+
+    #[test]
+    fn cycles() {
+        let mut p = pomodoro();
+        assert!(!p.check_end());
+        end_phase(&mut p);
+        assert!(p.check_end());
+        assert!(p.phase == Phase::ShortBreak);
+        assert_eq!(p.completed, 1);
+        assert!(p.is_urgent);
+        // The break waits to be started.
+        assert!(!p.countdown.is_running());
+        assert_eq!(p.countdown.duration, 5 * MIN);
+
+        end_phase(&mut p);
+        assert!(!p.is_urgent);
+        assert!(p.check_end());
+        assert!(p.phase == Phase::Work);
+
+        end_phase(&mut p);
+        assert!(p.check_end());
+        assert!(p.phase == Phase::LongBreak);
+        assert_eq!(p.completed, 2);
+        assert_eq!(p.countdown.duration, 15 * MIN);
+
+        end_phase(&mut p);
+        assert!(p.check_end());
+        assert!(p.phase == Phase::Work);
+        assert_eq!(p.completed, 0);
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut p = pomodoro();
+        p.switch(Phase::ShortBreak);
+        p.completed = 1;
+        p.start();
+        p.countdown.set_elapsed(MIN);
+
+        let mut restored = pomodoro();
+        restored.restore(&p.state()).unwrap();
+        assert!(restored.phase == Phase::ShortBreak);
+        assert_eq!(restored.completed, 1);
+        assert_eq!(restored.countdown.duration, 5 * MIN);
+        assert!(restored.countdown.is_running());
+        let elapsed = restored.countdown.elapsed();
+        assert!(elapsed >= MIN && elapsed < MIN + Duration::from_secs(1));
+
+        p.countdown.pause();
+        p.is_urgent = true;
+        let mut restored = pomodoro();
+        restored.restore(&p.state()).unwrap();
+        assert!(!restored.countdown.is_running());
+        assert!(restored.countdown.is_paused());
+        assert!(restored.is_urgent);
+    }
+
+    #[test]
+    fn restore_counts_the_time_since_the_save() {
+        // The phase was running when gstatus stopped 5 seconds ago.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let s = format!(
+            "phase=work\ncompleted=1\nelapsed_ms=1000\nstarted_ms={}\nurgent=false\n",
+            (now - Duration::from_secs(5)).as_millis()
+        );
+        let mut p = pomodoro();
+        p.restore(&s).unwrap();
+        assert!(p.countdown.is_running());
+        let elapsed = p.countdown.elapsed();
+        assert!(elapsed >= Duration::from_secs(6) && elapsed < Duration::from_secs(7));
+    }
+
+    #[test]
+    fn restore_invalid() {
+        assert!(pomodoro().restore("phase=nap\n").is_err());
+        assert!(pomodoro().restore("completed=many\n").is_err());
+        assert!(pomodoro().restore("work\n").is_err());
+        // Keys of other versions are ignored.
+        assert!(pomodoro().restore("theme=dark\n").is_ok());
+    }
+}
@@ -0,0 +1,293 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::convert::TryFrom;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
+use std::{io, mem};
+
+/// Identifies what a file descriptor or a deadline belongs to.
+pub(crate) type Token = u64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ReadyKind {
+    /// The file descriptor can be read from.
+    Readable,
+    /// The file descriptor reported a hang up or an error. It won't be polled anymore.
+    Hangup,
+    /// The deadline expired. It is cleared, so a new one must be set if needed.
+    Deadline,
+}
+
+pub(crate) struct Ready {
+    pub token: Token,
+    pub kind: ReadyKind,
+}
+
+/// Waits for file descriptors to become readable and for deadlines to expire. File descriptors
+/// are registered once, and each token can have one deadline at a time.
+pub(crate) struct Reactor {
+    epoll: libc::c_int,
+    fds: HashMap<Token, RawFd>,
+    deadlines: HashMap<Token, Instant>,
+    /// Contains every deadline in `deadlines`, but also the ones that were changed since they
+    /// were pushed. Those are skipped when they reach the top.
+    heap: BinaryHeap<Reverse<(Instant, Token)>>,
+}
+
+impl Reactor {
+    const MAX_EVENTS: usize = 16;
+
+    pub(crate) fn new() -> io::Result<Self> {
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            epoll,
+            fds: HashMap::new(),
+            deadlines: HashMap::new(),
+            heap: BinaryHeap::new(),
+        })
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, token: Token) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: token,
+        };
+        let ret = unsafe { libc::epoll_ctl(self.epoll, op, fd, &mut event) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Starts polling a file descriptor for reading.
+    pub(crate) fn register(&mut self, fd: RawFd, token: Token) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, token)?;
+        self.fds.insert(token, fd);
+        Ok(())
+    }
+
+    /// Stops polling the file descriptor of a token. It may already be closed, in which case
+    /// epoll already forgot about it.
+    pub(crate) fn deregister(&mut self, token: Token) -> io::Result<()> {
+        let fd = match self.fds.remove(&token) {
+            Some(fd) => fd,
+            None => return Ok(()),
+        };
+        match self.ctl(libc::EPOLL_CTL_DEL, fd, token) {
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EBADF | libc::ENOENT)) => Ok(()),
+            r => r,
+        }
+    }
+
+    /// Makes a token poll another file descriptor, or none. If it is the same number, the file
+    /// descriptor may have been closed and the number reused, so it is registered again if
+    /// needed.
+    pub(crate) fn set_fd(&mut self, token: Token, fd: Option<RawFd>) -> io::Result<()> {
+        let old = self.fds.get(&token).copied();
+        match fd {
+            Some(fd) if old == Some(fd) => match self.ctl(libc::EPOLL_CTL_MOD, fd, token) {
+                Err(err) if err.raw_os_error() == Some(libc::ENOENT) => {
+                    self.ctl(libc::EPOLL_CTL_ADD, fd, token)
+                }
+                r => r,
+            },
+            Some(fd) => {
+                self.deregister(token)?;
+                self.register(fd, token)
+            }
+            None => self.deregister(token),
+        }
+    }
+
+    /// Sets or clears the deadline of a token, replacing the previous one.
+    pub(crate) fn set_deadline(&mut self, token: Token, deadline: Option<Instant>) {
+        match deadline {
+            Some(d) => {
+                if self.deadlines.insert(token, d) != Some(d) {
+                    self.heap.push(Reverse((d, token)));
+                }
+            }
+            None => {
+                self.deadlines.remove(&token);
+            }
+        }
+    }
+
+    /// Returns the earliest deadline, dropping the outdated heap entries on the way.
+    fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some(Reverse((d, token))) = self.heap.peek() {
+            if self.deadlines.get(token) == Some(d) {
+                return Some(*d);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// Moves all of the deadlines up to `now` to `ready`.
+    fn expire(&mut self, now: Instant, ready: &mut Vec<Ready>) {
+        while let Some(d) = self.next_deadline() {
+            if d > now {
+                break;
+            }
+            let Reverse((_, token)) = self.heap.pop().unwrap();
+            self.deadlines.remove(&token);
+            ready.push(Ready {
+                token,
+                kind: ReadyKind::Deadline,
+            });
+        }
+    }
+
+    /// Waits until at least one file descriptor is ready or one deadline expires, and returns
+    /// everything that is ready at that point.
+    pub(crate) fn wait(&mut self) -> io::Result<Vec<Ready>> {
+        let timeout_ms = match self.next_deadline() {
+            Some(d) => {
+                let diff = d.saturating_duration_since(Instant::now());
+                // Round up, otherwise we would wake up early and spin until the deadline.
+                let ms = diff.as_nanos().div_ceil(1_000_000);
+                libc::c_int::try_from(ms).unwrap_or(libc::c_int::MAX)
+            }
+            None => -1,
+        };
+
+        let mut events: [libc::epoll_event; Reactor::MAX_EVENTS] = unsafe { mem::zeroed() };
+        let ret = unsafe {
+            libc::epoll_wait(
+                self.epoll,
+                events.as_mut_ptr(),
+                Reactor::MAX_EVENTS as libc::c_int,
+                timeout_ms,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ready = Vec::new();
+        for event in &events[..usize::try_from(ret).unwrap()] {
+            let token = event.u64;
+            let flags = event.events;
+            let kind = if flags & (libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0 {
+                // A level-triggered file descriptor would be reported again and again.
+                self.deregister(token)?;
+                ReadyKind::Hangup
+            } else {
+                ReadyKind::Readable
+            };
+            ready.push(Ready { token, kind });
+        }
+        self.expire(Instant::now(), &mut ready);
+        Ok(ready)
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::close(self.epoll) };
+        if ret == -1 {
+            eprintln!("failed to close epoll: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+        assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    fn tokens(ready: &[Ready], kind: ReadyKind) -> Vec<Token> {
+        let mut tokens: Vec<Token> = ready
+            .iter()
+            .filter(|r| r.kind == kind)
+            .map(|r| r.token)
+            .collect();
+        tokens.sort_unstable();
+        tokens
+    }
+
+    #[test]
+    fn fire_expired_deadlines_together() {
+        let mut reactor = Reactor::new().unwrap();
+        let now = Instant::now();
+        reactor.set_deadline(1, Some(now));
+        reactor.set_deadline(2, Some(now + Duration::from_millis(5)));
+        reactor.set_deadline(3, Some(now - Duration::from_secs(1)));
+        reactor.set_deadline(4, Some(now + Duration::from_secs(3600)));
+        // The first wait wakes up for the earliest deadline, so sleep past the second one.
+        std::thread::sleep(Duration::from_millis(10));
+        let ready = reactor.wait().unwrap();
+        assert_eq!(tokens(&ready, ReadyKind::Deadline), [1, 2, 3]);
+        // Expired deadlines are cleared, the others stay.
+        assert_eq!(
+            reactor.next_deadline(),
+            Some(now + Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn skip_stale_deadlines() {
+        let mut reactor = Reactor::new().unwrap();
+        let now = Instant::now();
+        // Both tokens are moved after their first deadline, which stays in the heap.
+        reactor.set_deadline(1, Some(now));
+        reactor.set_deadline(1, Some(now + Duration::from_secs(3600)));
+        reactor.set_deadline(2, Some(now));
+        reactor.set_deadline(2, None);
+        reactor.set_deadline(3, Some(now + Duration::from_millis(20)));
+        let ready = reactor.wait().unwrap();
+        assert!(Instant::now() >= now + Duration::from_millis(20));
+        assert_eq!(tokens(&ready, ReadyKind::Deadline), [3]);
+        assert_eq!(
+            reactor.next_deadline(),
+            Some(now + Duration::from_secs(3600))
+        );
+        // Setting the same deadline again doesn't add an entry.
+        reactor.set_deadline(1, Some(now + Duration::from_secs(3600)));
+        assert_eq!(reactor.heap.len(), 1);
+    }
+
+    #[test]
+    fn report_readable_fd() {
+        let mut reactor = Reactor::new().unwrap();
+        let (reader, mut writer) = pipe();
+        reactor.register(reader.as_raw_fd(), 7).unwrap();
+        writer.write_all(b"x").unwrap();
+        let ready = reactor.wait().unwrap();
+        assert_eq!(tokens(&ready, ReadyKind::Readable), [7]);
+        // Registering the same file descriptor again only modifies it.
+        reactor.set_fd(7, Some(reader.as_raw_fd())).unwrap();
+        assert_eq!(reactor.fds.get(&7), Some(&reader.as_raw_fd()));
+    }
+
+    #[test]
+    fn report_and_deregister_hangup() {
+        let mut reactor = Reactor::new().unwrap();
+        let (reader, writer) = pipe();
+        reactor.register(reader.as_raw_fd(), 7).unwrap();
+        drop(writer);
+        let ready = reactor.wait().unwrap();
+        assert_eq!(tokens(&ready, ReadyKind::Hangup), [7]);
+        assert!(tokens(&ready, ReadyKind::Readable).is_empty());
+        assert!(!reactor.fds.contains_key(&7));
+        // The file descriptor isn't reported again.
+        reactor.set_deadline(8, Some(Instant::now() + Duration::from_millis(10)));
+        let ready = reactor.wait().unwrap();
+        assert!(ready.iter().all(|r| r.token == 8));
+    }
+}
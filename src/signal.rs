@@ -0,0 +1,105 @@
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::{io, mem};
+
+/// Blocks signals and returns their set.
+fn block(signals: &[libc::c_int]) -> io::Result<libc::sigset_t> {
+    let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+    unsafe { libc::sigemptyset(&mut mask) };
+    for signal in signals {
+        let ret = unsafe { libc::sigaddset(&mut mask, *signal) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let ret = unsafe { libc::sigprocmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(mask)
+}
+
+/// Unblocks all of the signals. Child processes should call it before `exec`, as they inherit
+/// the blocked signals. It is async-signal-safe.
+pub(crate) fn unblock_all() -> io::Result<()> {
+    let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+    unsafe { libc::sigemptyset(&mut mask) };
+    let ret = unsafe { libc::sigprocmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut()) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives signals through a file descriptor instead of a handler.
+pub(crate) struct SignalFd {
+    fd: libc::c_int,
+}
+
+impl SignalFd {
+    /// Blocks the signals so that their default action doesn't happen, and opens a file
+    /// descriptor that receives them. Child processes inherit the blocked signals.
+    pub(crate) fn open(signals: &[libc::c_int]) -> io::Result<Self> {
+        let mask = block(signals)?;
+        let fd = unsafe { libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    /// Replaces the signals that are received. The signals that are not received anymore stay
+    /// blocked.
+    pub(crate) fn set_signals(&self, signals: &[libc::c_int]) -> io::Result<()> {
+        let mask = block(signals)?;
+        let ret = unsafe { libc::signalfd(self.fd, &mask, 0) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns the pending signals, in the order they were received.
+    pub(crate) fn read(&self) -> io::Result<Vec<libc::c_int>> {
+        let mut signals = Vec::new();
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let ret = unsafe {
+                libc::read(
+                    self.fd,
+                    &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                    mem::size_of_val(&info),
+                )
+            };
+            if ret == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(signals);
+                }
+                return Err(err);
+            }
+            if usize::try_from(ret).unwrap() != mem::size_of_val(&info) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated signal information",
+                ));
+            }
+            signals.push(libc::c_int::try_from(info.ssi_signo).unwrap());
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::close(self.fd) };
+        if ret == -1 {
+            eprintln!("failed to close signalfd: {}", io::Error::last_os_error());
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+use std::{io, iter, mem};
+
+use crate::module::{self, Block, ClickEvent, Module};
+use crate::uevent;
+
+enum State {
+    /// `backoff` is how long we waited before creating the module.
+    Running {
+        module: Box<dyn Module>,
+        started_at: Instant,
+        backoff: Duration,
+    },
+    /// The module couldn't be created, or its file descriptor hung up. `backoff` is how long we
+    /// waited before `retry_at`. A module is absent if its device doesn't exist, in which case
+    /// it isn't rendered at all.
+    Failed {
+        retry_at: Instant,
+        backoff: Duration,
+        is_absent: bool,
+    },
+}
+
+/// Creates a module and creates it again after it fails, waiting longer after each failed
+/// attempt. A failed module is rendered as a dimmed block, and a module whose device doesn't
+/// exist isn't rendered.
+pub(crate) struct Supervisor {
+    /// The name of the module's section in the configuration file.
+    section: String,
+    name: String,
+    create: Box<dyn FnMut() -> io::Result<Box<dyn Module>>>,
+    state: State,
+    /// Whether the module was created since the last call to `take_started`.
+    started: bool,
+    /// Whether the module was called since the last call to `take_touched`.
+    touched: bool,
+}
+
+impl Supervisor {
+    const MIN_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+    /// A module that hangs up sooner after it was created keeps waiting longer before the next
+    /// attempt, as it would be created again and again otherwise.
+    const STABLE_TIME: Duration = Duration::from_secs(60);
+
+    pub(crate) fn new<F>(section: &str, name: &str, create: F) -> Self
+    where
+        F: FnMut() -> io::Result<Box<dyn Module>> + 'static,
+    {
+        let mut r = Self {
+            section: section.to_owned(),
+            name: name.to_owned(),
+            create: Box::new(create),
+            state: State::Failed {
+                retry_at: Instant::now(),
+                backoff: Duration::ZERO,
+                is_absent: false,
+            },
+            started: false,
+            touched: false,
+        };
+        r.start(Duration::ZERO);
+        r
+    }
+
+    /// Tries to create the module. `backoff` is how long we waited after the previous attempt.
+    fn start(&mut self, backoff: Duration) {
+        self.touched = true;
+        match (self.create)() {
+            Ok(module) => {
+                self.state = State::Running {
+                    module,
+                    started_at: Instant::now(),
+                    backoff,
+                };
+                self.started = true;
+            }
+            Err(err) => {
+                let backoff = (backoff * 2).clamp(Supervisor::MIN_BACKOFF, Supervisor::MAX_BACKOFF);
+                // Hardware that this computer doesn't have, such as the backlight of a desktop
+                // computer, is not worth a block. It may still be plugged in later. Any other
+                // error, such as a missing file that the configuration names, is shown.
+                let is_absent = module::is_no_device(&err);
+                let was_absent = matches!(
+                    self.state,
+                    State::Failed {
+                        is_absent: true,
+                        ..
+                    }
+                );
+                if !is_absent {
+                    eprintln!(
+                        "failed to create the {} module, retrying in {}s: {:?}",
+                        self.name,
+                        backoff.as_secs(),
+                        err
+                    );
+                } else if !was_absent {
+                    eprintln!("hiding the {} module: {:?}", self.name, err);
+                }
+                self.state = State::Failed {
+                    retry_at: Instant::now() + backoff,
+                    backoff,
+                    is_absent,
+                };
+            }
+        }
+    }
+
+    pub(crate) fn section(&self) -> &str {
+        &self.section
+    }
+
+    /// Returns `true` once after the module was created. Its file descriptor and its kernel event
+    /// subscriptions may have changed.
+    pub(crate) fn take_started(&mut self) -> bool {
+        mem::take(&mut self.started)
+    }
+
+    /// Returns `true` once after the module was called in a way that may have changed its file
+    /// descriptor or its deadline.
+    pub(crate) fn take_touched(&mut self) -> bool {
+        mem::take(&mut self.touched)
+    }
+}
+
+impl Module for Supervisor {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        match &self.state {
+            State::Running { module, .. } => module.render(),
+            State::Failed {
+                is_absent: true, ..
+            } => Box::new(iter::empty()),
+            State::Failed { .. } => Box::new(iter::once(Block {
+                text: format!("{}: unavailable", self.name),
+                is_dimmed: true,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn update(&mut self) -> bool {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.update(),
+            State::Failed {
+                retry_at, backoff, ..
+            } => {
+                if Instant::now() < *retry_at {
+                    return false;
+                }
+                let backoff = *backoff;
+                self.start(backoff);
+                matches!(self.state, State::Running { .. })
+            }
+        }
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        match &self.state {
+            State::Running { module, .. } => module.pollable_fd(),
+            State::Failed { .. } => None,
+        }
+    }
+
+    /// Never fails, as the module is created again instead.
+    fn hangup(&mut self) -> io::Result<bool> {
+        self.touched = true;
+        let (err, backoff) = match &mut self.state {
+            State::Running {
+                module,
+                started_at,
+                backoff,
+            } => match module.hangup() {
+                Ok(dirty) => return Ok(dirty),
+                Err(err) if started_at.elapsed() >= Supervisor::STABLE_TIME => {
+                    (err, Duration::ZERO)
+                }
+                Err(err) => (err, *backoff),
+            },
+            State::Failed { .. } => return Ok(false),
+        };
+        // The file descriptor is probably gone for good, for example if a device was unplugged.
+        let backoff = (backoff * 2).clamp(Supervisor::MIN_BACKOFF, Supervisor::MAX_BACKOFF);
+        eprintln!(
+            "the {} module stopped working, retrying in {}s: {:?}",
+            self.name,
+            backoff.as_secs(),
+            err
+        );
+        self.state = State::Failed {
+            retry_at: Instant::now() + backoff,
+            backoff,
+            is_absent: false,
+        };
+        Ok(true)
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        match &self.state {
+            State::Running { module, .. } => module.timeout(),
+            State::Failed { retry_at, .. } => Some(*retry_at),
+        }
+    }
+
+    fn expiry(&self) -> Option<Instant> {
+        match &self.state {
+            State::Running { module, .. } => module.expiry(),
+            State::Failed { .. } => None,
+        }
+    }
+
+    fn uevent_subscriptions(&self) -> Vec<uevent::Subscription> {
+        match &self.state {
+            State::Running { module, .. } => module.uevent_subscriptions(),
+            State::Failed { .. } => Vec::new(),
+        }
+    }
+
+    fn uevent(&mut self, event: &uevent::Event) -> bool {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.uevent(event),
+            State::Failed { .. } => false,
+        }
+    }
+
+    fn uevent_overflow(&mut self) -> bool {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.uevent_overflow(),
+            State::Failed { .. } => false,
+        }
+    }
+
+    fn time_changed(&mut self) -> bool {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.time_changed(),
+            State::Failed { .. } => false,
+        }
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.click(event),
+            State::Failed { .. } => false,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        self.touched = true;
+        match &mut self.state {
+            State::Running { module, .. } => module.set(key, value),
+            State::Failed { .. } => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the module is unavailable",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(supervisor: &Supervisor) -> Vec<String> {
+        supervisor.render().map(|b| b.text).collect()
+    }
+
+    #[test]
+    fn only_missing_devices_are_hidden() {
+        let absent = Supervisor::new("backlight", "Backlight", || {
+            Err(module::no_device("no backlight device found"))
+        });
+        assert!(texts(&absent).is_empty());
+
+        // A missing file is a mistake in the configuration unless the module says otherwise.
+        let failed = Supervisor::new("clock:Nowhere", "Nowhere", || {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such time zone"))
+        });
+        assert_eq!(texts(&failed), ["Nowhere: unavailable"]);
+    }
+}
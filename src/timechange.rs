@@ -0,0 +1,251 @@
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+use std::{io, mem};
+
+// See include/uapi/linux/timerfd.h in the Linux source tree.
+const TFD_TIMER_CANCEL_ON_SET: libc::c_int = 1 << 1;
+
+pub(crate) fn clock_gettime(clock: libc::clockid_t) -> io::Result<Duration> {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    let ret = unsafe { libc::clock_gettime(clock, &mut ts) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Duration::new(
+        u64::try_from(ts.tv_sec).unwrap_or(0),
+        u32::try_from(ts.tv_nsec).unwrap_or(0),
+    ))
+}
+
+/// Returns how long the system has been suspended since it booted.
+fn suspended_time() -> io::Result<Duration> {
+    let boottime = clock_gettime(libc::CLOCK_BOOTTIME)?;
+    let monotonic = clock_gettime(libc::CLOCK_MONOTONIC)?;
+    Ok(boottime.saturating_sub(monotonic))
+}
+
+/// What happened to a `WallTimer` since it was armed.
+pub(crate) enum Expiration {
+    Pending,
+    Expired,
+    /// The system clock was set, so the timer must be armed again.
+    Cancelled,
+}
+
+/// A timer that expires at a time of the system clock. Unlike monotonic deadlines, it expires
+/// right after a resume if it was due during the suspend, and it is cancelled when the clock is
+/// set.
+pub(crate) struct WallTimer {
+    fd: libc::c_int,
+}
+
+impl WallTimer {
+    pub(crate) fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    /// Arms the timer to expire once at a time since the Unix epoch.
+    pub(crate) fn set(&self, at: Duration) -> io::Result<()> {
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: libc::time_t::try_from(at.as_secs()).unwrap(),
+                tv_nsec: libc::c_long::from(at.subsec_nanos()),
+            },
+        };
+        let ret = unsafe {
+            libc::timerfd_settime(
+                self.fd,
+                libc::TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET,
+                &spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Consumes the expiration of the timer, if any. The timer doesn't expire again until it is
+    /// armed, even after a cancellation.
+    pub(crate) fn read(&self) -> io::Result<Expiration> {
+        let mut expirations: u64 = 0;
+        let ret = unsafe {
+            libc::read(
+                self.fd,
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                mem::size_of_val(&expirations),
+            )
+        };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ECANCELED) => Ok(Expiration::Cancelled),
+                Some(libc::EAGAIN) => Ok(Expiration::Pending),
+                _ => Err(err),
+            };
+        }
+        Ok(Expiration::Expired)
+    }
+}
+
+impl AsRawFd for WallTimer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for WallTimer {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::close(self.fd) };
+        if ret == -1 {
+            eprintln!("failed to close timerfd: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+/// Notices when the system clock is set, for example by NTP, and when the system resumes from
+/// suspend. Deadlines are monotonic, so they don't account for either.
+pub(crate) struct ClockWatcher {
+    timer: WallTimer,
+    /// The value of `suspended_time` at the last check.
+    suspended: Duration,
+}
+
+impl ClockWatcher {
+    /// How often to check whether the system was suspended. The timer follows the real time, so
+    /// it also expires right after a resume if the check was due during the suspend.
+    const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// The difference between the monotonic clock and the boot clock that counts as a suspend.
+    const SUSPEND_THRESHOLD: Duration = Duration::from_secs(1);
+
+    pub(crate) fn open() -> io::Result<Self> {
+        let r = Self {
+            timer: WallTimer::open()?,
+            suspended: suspended_time()?,
+        };
+        r.arm()?;
+        Ok(r)
+    }
+
+    fn arm(&self) -> io::Result<()> {
+        let at = clock_gettime(libc::CLOCK_REALTIME)? + ClockWatcher::CHECK_INTERVAL;
+        self.timer.set(at)
+    }
+
+    /// Returns `true` if the clock was set or the system resumed since the last call.
+    pub(crate) fn read(&mut self) -> io::Result<bool> {
+        let mut changed = match self.timer.read()? {
+            Expiration::Pending => return Ok(false),
+            Expiration::Expired => false,
+            Expiration::Cancelled => true,
+        };
+        self.arm()?;
+        let suspended = suspended_time()?;
+        if suspended >= self.suspended + ClockWatcher::SUSPEND_THRESHOLD {
+            changed = true;
+        }
+        self.suspended = suspended;
+        Ok(changed)
+    }
+}
+
+impl AsRawFd for ClockWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+/// Notices when the local time zone changes. `/etc/localtime` is usually a symbolic link that is
+/// replaced, so its directory is watched.
+pub(crate) struct ZoneWatcher {
+    /// An inotify instance.
+    fd: libc::c_int,
+}
+
+impl ZoneWatcher {
+    const DIR: &'static str = "/etc";
+    const NAME: &'static [u8] = b"localtime";
+
+    pub(crate) fn open() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let r = Self { fd };
+        let dir = CString::new(ZoneWatcher::DIR).unwrap();
+        let mask = libc::IN_CREATE
+            | libc::IN_MOVED_TO
+            | libc::IN_CLOSE_WRITE
+            | libc::IN_ATTRIB
+            | libc::IN_DELETE;
+        let ret = unsafe { libc::inotify_add_watch(r.fd, dir.as_ptr(), mask) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(r)
+    }
+
+    /// Returns `true` if `/etc/localtime` changed since the last call.
+    pub(crate) fn read(&self) -> io::Result<bool> {
+        // Large enough for at least one event with the longest name.
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let ret =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if ret == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(changed);
+                }
+                return Err(err);
+            }
+            let len = usize::try_from(ret).unwrap();
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= len {
+                let event = unsafe {
+                    (buf.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+                };
+                let name_start = offset + mem::size_of::<libc::inotify_event>();
+                let name_end = (name_start + usize::try_from(event.len).unwrap()).min(len);
+                let name = &buf[name_start..name_end];
+                // The name is padded with NUL bytes.
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                // Events were lost if the queue overflowed.
+                if name == ZoneWatcher::NAME || event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    changed = true;
+                }
+                offset = name_end;
+            }
+        }
+    }
+}
+
+impl AsRawFd for ZoneWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for ZoneWatcher {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::close(self.fd) };
+        if ret == -1 {
+            eprintln!("failed to close inotify: {}", io::Error::last_os_error());
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::io;
+use std::iter;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use crate::command::spawn_detached;
+use crate::config;
+use crate::module::{self, Block, ClickEvent, Module};
+use crate::timechange;
+
+/// Formats a number of seconds as `mm:ss`, or `h:mm:ss` from one hour.
+pub(crate) fn format_secs(secs: u64) -> String {
+    if secs >= 60 * 60 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Returns the time since boot, which includes the time spent suspended unlike `Instant`.
+fn boot_time() -> Duration {
+    // Linux supports this clock since 2.6.39.
+    timechange::clock_gettime(libc::CLOCK_BOOTTIME).unwrap()
+}
+
+/// The time counted by a timer or by a pomodoro phase, which can be paused. It counts down if
+/// its duration isn't zero, and up otherwise.
+pub(crate) struct Countdown {
+    pub duration: Duration,
+    /// The time counted before the last start.
+    elapsed: Duration,
+    /// The boot time when it was started, if it is running.
+    started: Option<Duration>,
+}
+
+impl Countdown {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            elapsed: Duration::ZERO,
+            started: None,
+        }
+    }
+
+    pub(crate) fn is_countdown(&self) -> bool {
+        !self.duration.is_zero()
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Returns `true` if it was started and paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.started.is_none() && !self.elapsed.is_zero()
+    }
+
+    /// Returns `true` if it is running and reached zero.
+    pub(crate) fn has_ended(&self) -> bool {
+        self.is_running() && self.is_countdown() && self.elapsed() >= self.duration
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        let running = self
+            .started
+            .map_or(Duration::ZERO, |s| boot_time().saturating_sub(s));
+        self.elapsed + running
+    }
+
+    /// Returns the time that is shown: the remaining time of a countdown, or the elapsed time of
+    /// a stopwatch.
+    fn shown(&self) -> Duration {
+        if self.is_countdown() {
+            self.duration.saturating_sub(self.elapsed())
+        } else {
+            self.elapsed()
+        }
+    }
+
+    /// Returns the shown number of seconds. A countdown shows the started seconds, so that it
+    /// shows 00:00 only once it ends.
+    pub(crate) fn shown_secs(&self) -> u64 {
+        let shown = self.shown();
+        if self.is_countdown() && shown.subsec_nanos() > 0 {
+            shown.as_secs() + 1
+        } else {
+            shown.as_secs()
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        if self.started.is_none() {
+            self.started = Some(boot_time());
+        }
+    }
+
+    pub(crate) fn pause(&mut self) {
+        self.elapsed = self.elapsed();
+        self.started = None;
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.started = None;
+    }
+
+    /// Sets the time counted so far, without starting or pausing.
+    pub(crate) fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+        if self.started.is_some() {
+            self.started = Some(boot_time());
+        }
+    }
+
+    /// Returns when the shown second changes, if it is running.
+    pub(crate) fn timeout(&self) -> Option<Instant> {
+        self.started?;
+        let subsec = Duration::from_nanos(self.shown().subsec_nanos().into());
+        let wait = if !self.is_countdown() {
+            Duration::from_secs(1) - subsec
+        } else if subsec.is_zero() {
+            Duration::from_secs(1)
+        } else {
+            subsec
+        };
+        Some(Instant::now() + wait)
+    }
+
+    /// Returns when a running countdown reaches zero.
+    pub(crate) fn expiry(&self) -> Option<Instant> {
+        if !self.is_running() || !self.is_countdown() {
+            return None;
+        }
+        Some(Instant::now() + self.duration.saturating_sub(self.elapsed()))
+    }
+}
+
+/// A countdown, or a stopwatch if its duration is zero. The left button starts and pauses it,
+/// the right button resets it, and scrolling changes the duration.
+pub(crate) struct Timer {
+    name: String,
+    countdown: Countdown,
+    /// How much scrolling changes the duration.
+    step: Duration,
+    /// A shell command that runs when the countdown expires.
+    notify: Option<String>,
+    is_expired: bool,
+}
+
+impl Timer {
+    const DEFAULT_DURATION: Duration = Duration::from_secs(25 * 60);
+    const DEFAULT_STEP: Duration = Duration::from_secs(60);
+    /// The shortest duration that scrolling sets, if the step is shorter.
+    const MIN_DURATION: Duration = Duration::from_secs(1);
+
+    /// Creates the module of a `[timer:<name>]` section. The timer is stopped.
+    pub(crate) fn new(name: &str, config: &config::Section) -> Self {
+        let duration = config
+            .duration_or("duration", Some(Timer::DEFAULT_DURATION))
+            .unwrap_or_default();
+        Self {
+            name: name.to_owned(),
+            countdown: Countdown::new(duration),
+            step: config
+                .duration_or("step", Some(Timer::DEFAULT_STEP))
+                .unwrap_or(Timer::DEFAULT_STEP),
+            notify: config.get("notify").map(str::to_owned),
+            is_expired: false,
+        }
+    }
+
+    fn start(&mut self) {
+        if !self.is_expired {
+            self.countdown.start();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.countdown.reset();
+        self.is_expired = false;
+    }
+
+    /// Expires the countdown if it reached zero.
+    fn check_expiry(&mut self) {
+        if !self.countdown.has_ended() {
+            return;
+        }
+        self.countdown.pause();
+        self.is_expired = true;
+        if let Some(notify) = &self.notify {
+            if let Err(err) = spawn_detached(notify) {
+                eprintln!("failed to run the notify command of {}: {err}", self.name);
+            }
+        }
+    }
+}
+
+impl Module for Timer {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let mut text = format!("{} {}", self.name, format_secs(self.countdown.shown_secs()));
+        if self.countdown.is_paused() && !self.is_expired {
+            text.push_str(" (paused)");
+        }
+        let block = Block {
+            text,
+            is_urgent: self.is_expired,
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        self.check_expiry();
+        // The shown time changes every second while the timer runs.
+        self.countdown.is_running() || self.is_expired
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        self.countdown.timeout()
+    }
+
+    fn expiry(&self) -> Option<Instant> {
+        self.countdown.expiry()
+    }
+
+    fn time_changed(&mut self) -> bool {
+        // The countdown may have expired while the system was suspended.
+        self.update()
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        let countdown = &mut self.countdown;
+        match event.button {
+            1 if self.is_expired => self.reset(),
+            1 if countdown.is_running() => countdown.pause(),
+            1 => self.start(),
+            3 => self.reset(),
+            4 => countdown.duration += self.step,
+            // Scrolling doesn't turn a countdown into a stopwatch.
+            5 if countdown.is_countdown() => {
+                let min = self.step.max(Timer::MIN_DURATION).min(countdown.duration);
+                countdown.duration = countdown.duration.saturating_sub(self.step).max(min);
+            }
+            _ => return false,
+        }
+        self.check_expiry();
+        true
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        match key {
+            "running" => match value {
+                "true" => self.start(),
+                "false" => self.countdown.pause(),
+                "toggle" if self.countdown.is_running() => self.countdown.pause(),
+                "toggle" => self.start(),
+                _ => return Err(module::invalid_value(key, value)),
+            },
+            "duration" => {
+                self.countdown.duration = config::parse_duration(value)
+                    .ok_or_else(|| module::invalid_value(key, value))?;
+            }
+            "elapsed" => {
+                let elapsed = config::parse_duration(value)
+                    .ok_or_else(|| module::invalid_value(key, value))?;
+                self.is_expired = false;
+                self.countdown.set_elapsed(elapsed);
+            }
+            _ => return Err(module::unknown_option(key)),
+        }
+        self.check_expiry();
+        Ok(true)
+    }
+}
@@ -1,4 +1,5 @@
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
 use std::ptr;
 use std::{collections::HashMap, io, mem, os::unix::io::AsRawFd, os::unix::io::RawFd};
 
@@ -35,19 +36,196 @@ pub(crate) fn parse_vars(
     Ok(r)
 }
 
+/// The longest `ACTION@DEVPATH` header that `build_filter` can skip over.
+const FILTER_MAX_HEADER_LEN: u32 = 512;
+
+/// Returned by a filter to accept the whole datagram.
+const FILTER_ACCEPT: u32 = u32::MAX;
+
+fn bpf_stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// The prefix of the messages sent by udevd, instead of the `ACTION@DEVPATH` header.
+const UDEV_PREFIX: &[u8; 8] = b"libudev\0";
+
+/// Protects against a mismatch of the udev message format, stored in big-endian.
+const UDEV_MAGIC: u32 = 0xfeed_cafe;
+
+/// The size of `struct monitor_netlink_header` from libudev.
+const UDEV_HEADER_LEN: usize = 40;
+
+/// The hash used by udev for `filter_subsystem_hash` (MurmurHash2 with a zero seed).
+fn udev_hash(s: &str) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let data = s.as_bytes();
+    let mut h = data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_ne_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, b) in tail.iter().enumerate() {
+            h ^= u32::from(*b) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+/// Builds a classic BPF program that only accepts events from the given subsystems.
+///
+/// Messages from udevd contain a hash of the subsystem at a fixed offset, so they are handled
+/// first. The kernel always sends `ACTION@DEVPATH\0ACTION=...\0DEVPATH=...\0SUBSYSTEM=...\0`, so the
+/// `SUBSYSTEM` variable starts at `2 * p + 17` where `p` is the position of the first NUL byte.
+/// BPF can't loop, so the search for that byte is unrolled. Events with a longer header are
+/// accepted and left to be filtered in user space. Returns `None` if a subsystem name is too long
+/// for the jumps.
+pub(crate) fn build_filter(subsystems: &[&str]) -> Option<Vec<libc::sock_filter>> {
+    use libc::{BPF_ABS, BPF_B, BPF_IMM, BPF_IND, BPF_JA, BPF_JEQ, BPF_JMP, BPF_K};
+    use libc::{BPF_LD, BPF_LDX, BPF_RET, BPF_W};
+
+    // Messages from udevd.
+    let mut prog = Vec::new();
+    let prefix_hi = u32::from_be_bytes(UDEV_PREFIX[..4].try_into().unwrap());
+    let prefix_lo = u32::from_be_bytes(UDEV_PREFIX[4..].try_into().unwrap());
+    let hashes_len = u8::try_from(subsystems.len()).ok()?;
+    // Jump to the kernel message part if the prefix doesn't match.
+    prog.push(bpf_stmt(BPF_LD | BPF_W | BPF_ABS, 0));
+    prog.push(bpf_jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        prefix_hi,
+        0,
+        hashes_len.checked_add(7)?,
+    ));
+    prog.push(bpf_stmt(BPF_LD | BPF_W | BPF_ABS, 4));
+    prog.push(bpf_jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        prefix_lo,
+        0,
+        hashes_len.checked_add(5)?,
+    ));
+    // Drop the message if the magic doesn't match.
+    prog.push(bpf_stmt(BPF_LD | BPF_W | BPF_ABS, 8));
+    prog.push(bpf_jump(
+        BPF_JMP | BPF_JEQ | BPF_K,
+        UDEV_MAGIC,
+        0,
+        hashes_len.checked_add(1)?,
+    ));
+    prog.push(bpf_stmt(BPF_LD | BPF_W | BPF_ABS, 24));
+    for (i, subsystem) in subsystems.iter().enumerate() {
+        let jt = hashes_len - u8::try_from(i).unwrap();
+        prog.push(bpf_jump(
+            BPF_JMP | BPF_JEQ | BPF_K,
+            udev_hash(subsystem),
+            jt,
+            0,
+        ));
+    }
+    prog.push(bpf_stmt(BPF_RET | BPF_K, 0));
+    prog.push(bpf_stmt(BPF_RET | BPF_K, FILTER_ACCEPT));
+
+    // Messages from the kernel.
+    let compare_start = u32::try_from(prog.len()).unwrap() + FILTER_MAX_HEADER_LEN * 4 + 1;
+    for p in 0..FILTER_MAX_HEADER_LEN {
+        prog.push(bpf_stmt(BPF_LD | BPF_B | BPF_ABS, p));
+        prog.push(bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 2));
+        prog.push(bpf_stmt(BPF_LDX | BPF_W | BPF_IMM, 2 * p + 17));
+        // The offset is relative to the next instruction.
+        let next = u32::try_from(prog.len()).unwrap() + 1;
+        prog.push(bpf_stmt(BPF_JMP | BPF_JA, compare_start - next));
+    }
+    prog.push(bpf_stmt(BPF_RET | BPF_K, FILTER_ACCEPT));
+
+    // The X register now contains the offset of the SUBSYSTEM variable.
+    for subsystem in subsystems {
+        let pattern = format!("SUBSYSTEM={}\0", subsystem).into_bytes();
+        // Compare 4 bytes at a time, then the remaining bytes one by one. Loads are big-endian.
+        let mut loads = Vec::new();
+        let mut offset = 0;
+        while offset < pattern.len() {
+            let rest = &pattern[offset..];
+            let (size, value, len) = match rest.get(..4) {
+                Some(word) => (BPF_W, u32::from_be_bytes(word.try_into().unwrap()), 4),
+                None => (BPF_B, u32::from(rest[0]), 1),
+            };
+            loads.push((size, u32::try_from(offset).ok()?, value));
+            offset += len;
+        }
+        for (i, (size, offset, value)) in loads.iter().enumerate() {
+            // On a mismatch, skip the remaining comparisons and the return.
+            let jf = u8::try_from((loads.len() - i - 1) * 2 + 1).ok()?;
+            prog.push(bpf_stmt(BPF_LD | size | BPF_IND, *offset));
+            prog.push(bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, *value, 0, jf));
+        }
+        prog.push(bpf_stmt(BPF_RET | BPF_K, FILTER_ACCEPT));
+    }
+    prog.push(bpf_stmt(BPF_RET | BPF_K, 0));
+    Some(prog)
+}
+
+/// The netlink multicast group to receive events from.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Group {
+    /// Events sent by the kernel, as soon as they happen.
+    Kernel = 1,
+    /// Events sent by udevd after it processed them, with the properties added by udev rules.
+    Udev = 2,
+}
+
+impl Group {
+    /// Returns `Udev` if udevd is running, and `Kernel` otherwise as there would be no events.
+    pub(crate) fn udev_if_running() -> Self {
+        // This is how libudev checks it.
+        if Path::new("/run/udev/control").exists() {
+            Group::Udev
+        } else {
+            Group::Kernel
+        }
+    }
+}
+
 pub(crate) struct Socket {
     fd: libc::c_int,
+    /// Reused for every datagram, and grown when a bigger one is received.
+    buf: Vec<u8>,
 }
 
 pub(crate) struct Event {
-    pub header: String,
     pub vars: HashMap<String, String>,
 }
 
 impl Socket {
     /// Tries to open and bind a uevent socket. An error is returned if the socket could not be
     /// opened.
-    pub(crate) fn open_and_bind(non_blocking: bool, close_on_exec: bool) -> io::Result<Self> {
+    pub(crate) fn open_and_bind(
+        group: Group,
+        non_blocking: bool,
+        close_on_exec: bool,
+    ) -> io::Result<Self> {
         let mut flags = libc::SOCK_RAW;
         if non_blocking {
             flags |= libc::SOCK_NONBLOCK;
@@ -61,11 +239,29 @@ impl Socket {
         }
         // Construct the socket as early as possible to get RAII to automatically close it if there
         // is an error.
-        let socket = Self { fd };
+        let socket = Self {
+            fd,
+            buf: vec![0u8; 4096],
+        };
+
+        // Receive the credentials of the sender to make sure that it can be trusted.
+        let enable: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                &enable as *const libc::c_int as *const libc::c_void,
+                mem::size_of_val(&enable) as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
 
         let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
         addr.nl_family = libc::AF_NETLINK as u16;
-        addr.nl_groups = 1;
+        addr.nl_groups = group as u32;
         let ret = unsafe {
             libc::bind(
                 fd,
@@ -80,21 +276,86 @@ impl Socket {
         Ok(socket)
     }
 
-    pub(crate) fn recv_event(&self) -> io::Result<Event> {
-        let mut buf = vec![0u8; 4096];
+    /// Attaches a classic BPF program to the socket, replacing any previous one, so that the
+    /// kernel drops the events that it rejects.
+    pub(crate) fn attach_filter(&self, filter: &[libc::sock_filter]) -> io::Result<()> {
+        // The previous program is detached first, otherwise both would be charged to the socket's
+        // option memory, which is too small for two large programs.
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_DETACH_FILTER,
+                // The value is ignored but the kernel requires one.
+                &0 as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            // There was no program.
+            if err.raw_os_error() != Some(libc::ENOENT) {
+                return Err(err);
+            }
+        }
+
+        let prog = libc::sock_fprog {
+            len: u16::try_from(filter.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "filter is too long"))?,
+            filter: filter.as_ptr() as *mut libc::sock_filter,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &prog as *const libc::sock_fprog as *const libc::c_void,
+                mem::size_of_val(&prog) as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receives the next event. An error with the `ENOBUFS` code means that events were lost
+    /// because the receive queue overflowed. Other errors without a code mean that the datagram
+    /// was invalid or untrusted and was skipped.
+    pub(crate) fn recv_event(&mut self) -> io::Result<Event> {
+        // Find out the size of the next datagram without consuming it.
+        let ret = unsafe {
+            libc::recv(
+                self.fd,
+                ptr::null_mut(),
+                0,
+                libc::MSG_PEEK | libc::MSG_TRUNC,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = usize::try_from(ret).unwrap();
+        if self.buf.len() < len {
+            self.buf.resize(len, 0);
+        }
 
         let mut iov = libc::iovec {
-            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
-            iov_len: buf.len(),
+            iov_base: self.buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: self.buf.len(),
         };
 
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        // Use u64 to get the alignment required by cmsghdr.
+        let mut control = [0u64; 8];
+
         let mut hdr = libc::msghdr {
-            msg_name: ptr::null_mut(),
-            msg_namelen: 0,
+            msg_name: &mut addr as *mut libc::sockaddr_nl as *mut libc::c_void,
+            msg_namelen: mem::size_of_val(&addr) as libc::socklen_t,
             msg_iov: &mut iov as *mut libc::iovec,
             msg_iovlen: 1,
-            msg_control: ptr::null_mut(),
-            msg_controllen: 0,
+            msg_control: control.as_mut_ptr() as *mut libc::c_void,
+            msg_controllen: mem::size_of_val(&control),
             msg_flags: 0,
         };
 
@@ -116,27 +377,210 @@ impl Socket {
             ));
         }
 
-        let header_end = match buf[..read].iter().position(|b| *b == b'\0') {
-            Some(p) => p,
+        // Only trust root, as anyone can send a datagram to the multicast groups.
+        let mut uid = None;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&hdr) };
+        while !cmsg.is_null() {
+            let c = unsafe { &*cmsg };
+            if c.cmsg_level == libc::SOL_SOCKET && c.cmsg_type == libc::SCM_CREDENTIALS {
+                let cred =
+                    unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::ucred) };
+                uid = Some(cred.uid);
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&hdr, cmsg) };
+        }
+        if uid != Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "uevent sender is not root",
+            ));
+        }
+        let buf = &self.buf[..read];
+        if !buf.starts_with(UDEV_PREFIX) && addr.nl_pid != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "kernel uevent not sent by the kernel",
+            ));
+        }
+
+        parse_message(buf)
+    }
+}
+
+/// Parses a message sent either by the kernel or by udevd.
+pub(crate) fn parse_message(buf: &[u8]) -> io::Result<Event> {
+    if buf.starts_with(UDEV_PREFIX) {
+        return parse_udev_message(buf);
+    }
+
+    let header_end = match buf.iter().position(|b| *b == b'\0') {
+        Some(p) => p,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "missing header",
+            ));
+        }
+    };
+
+    // The header repeats the ACTION and DEVPATH variables.
+    let vars = parse_vars(&buf[(header_end + 1)..], b'\0')
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid uevent vars"))?;
+    Ok(Event { vars })
+}
+
+/// Parses a message sent by udevd, which starts with a `struct monitor_netlink_header`.
+fn parse_udev_message(buf: &[u8]) -> io::Result<Event> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if buf.len() < UDEV_HEADER_LEN {
+        return Err(invalid("truncated udev header"));
+    }
+    let word = |i: usize| buf[i..(i + 4)].try_into().unwrap();
+    if u32::from_be_bytes(word(8)) != UDEV_MAGIC {
+        return Err(invalid("invalid udev magic"));
+    }
+    let properties_off = u32::from_ne_bytes(word(16)) as usize;
+    let properties_len = u32::from_ne_bytes(word(20)) as usize;
+    let properties = properties_off
+        .checked_add(properties_len)
+        .and_then(|end| buf.get(properties_off..end))
+        .ok_or_else(|| invalid("invalid udev properties"))?;
+    let vars = parse_vars(properties, b'\0').map_err(|_| invalid("invalid udev properties"))?;
+    Ok(Event { vars })
+}
+
+/// Selects kernel events by their `SUBSYSTEM` and, optionally, `DEVTYPE` variables.
+pub(crate) struct Subscription {
+    pub subsystem: String,
+    pub devtype: Option<String>,
+}
+
+impl Subscription {
+    pub(crate) fn subsystem(subsystem: &str) -> Self {
+        Self {
+            subsystem: subsystem.to_owned(),
+            devtype: None,
+        }
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        if event.vars.get("SUBSYSTEM") != Some(&self.subsystem) {
+            return false;
+        }
+        match &self.devtype {
+            Some(devtype) => event.vars.get("DEVTYPE") == Some(devtype),
+            None => true,
+        }
+    }
+}
+
+/// What a subscriber is notified of.
+pub(crate) enum Notification<'a> {
+    Event(&'a Event),
+    /// Events were lost, so the subscriber should read the state of the devices again.
+    Overflow,
+}
+
+/// Receives kernel events once and hands them to every subscriber that is interested in them.
+/// Subscribers are identified by an index chosen by the caller.
+pub(crate) struct Hub {
+    socket: Socket,
+    subscriptions: Vec<(usize, Subscription)>,
+}
+
+impl Hub {
+    pub(crate) fn new(group: Group) -> io::Result<Self> {
+        let r = Self {
+            socket: Socket::open_and_bind(group, true, true)?,
+            subscriptions: Vec::new(),
+        };
+        // Drop everything until there are subscribers.
+        r.update_filter();
+        Ok(r)
+    }
+
+    pub(crate) fn subscribe(&mut self, subscriber: usize, subscription: Subscription) {
+        self.subscriptions.push((subscriber, subscription));
+        self.update_filter();
+    }
+
+    /// Makes the kernel drop the events that no subscriber is interested in, so that the process
+    /// doesn't wake up for them.
+    fn update_filter(&self) {
+        let mut subsystems: Vec<&str> = self
+            .subscriptions
+            .iter()
+            .map(|(_, s)| s.subsystem.as_str())
+            .collect();
+        subsystems.sort_unstable();
+        subsystems.dedup();
+        let filter = match build_filter(&subsystems) {
+            Some(f) => f,
             None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "missing header",
-                ));
+                eprintln!("failed to build the uevent filter");
+                return;
             }
         };
+        if let Err(err) = self.socket.attach_filter(&filter) {
+            eprintln!("failed to attach the uevent filter: {err}");
+        }
+    }
+
+    /// Removes all of the subscriptions of a subscriber.
+    pub(crate) fn unsubscribe(&mut self, subscriber: usize) {
+        self.subscriptions.retain(|(s, _)| *s != subscriber);
+        self.update_filter();
+    }
+
+    /// Receives all of the pending events and calls `handler` with each subscriber that is
+    /// interested in them, at most once per event and subscriber. Returns `true` if any call
+    /// returned `true`.
+    pub(crate) fn dispatch<F>(&mut self, mut handler: F) -> bool
+    where
+        F: FnMut(usize, Notification) -> bool,
+    {
+        let mut r = false;
+        loop {
+            let event = match self.socket.recv_event() {
+                Ok(e) => e,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    eprintln!("uevent receive queue overflowed");
+                    let mut notified: Vec<usize> = Vec::new();
+                    for (subscriber, _) in self.subscriptions.iter() {
+                        if !notified.contains(subscriber) {
+                            notified.push(*subscriber);
+                            r |= handler(*subscriber, Notification::Overflow);
+                        }
+                    }
+                    continue;
+                }
+                Err(err) if err.raw_os_error().is_some() => {
+                    eprintln!("failed to read uevent: {err}");
+                    break;
+                }
+                Err(err) => {
+                    // The datagram was consumed, so the next one can be read.
+                    eprintln!("ignoring uevent: {err}");
+                    continue;
+                }
+            };
+            let mut notified: Vec<usize> = Vec::new();
+            for (subscriber, subscription) in self.subscriptions.iter() {
+                if notified.contains(subscriber) || !subscription.matches(&event) {
+                    continue;
+                }
+                notified.push(*subscriber);
+                r |= handler(*subscriber, Notification::Event(&event));
+            }
+        }
+        r
+    }
+}
 
-        let header = std::str::from_utf8(&buf[..header_end])
-            .map_err(|_| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "missing or invalid uevent header",
-                )
-            })?
-            .to_owned();
-        let vars = parse_vars(&buf[(header_end + 1)..read], b'\0')
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid uevent vars"))?;
-        Ok(Event { header, vars })
+impl AsRawFd for Hub {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
     }
 }
 
@@ -157,3 +601,131 @@ impl Drop for Socket {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udev_hash_matches_libudev() {
+        // The constants of the filters attached by libudev.
+        for (s, hash) in [
+            ("power_supply", 0xb1ac_bcca),
+            ("backlight", 0xc370_b302),
+            ("sound", 0xd196_ab6e),
+            ("net", 0xa74d_3cc8),
+            ("a", 0x9268_5f5e),
+            ("ab", 0x1aa1_4063),
+            ("abc", 0x1357_7c9b),
+            ("abcd", 0x2687_3021),
+            ("abcde", 0x5f09_a8de),
+        ] {
+            assert_eq!(udev_hash(s), hash, "{}", s);
+        }
+    }
+
+    /// Runs a classic BPF program with the instructions that `build_filter` uses. Like the
+    /// kernel, a load past the end of the packet rejects it.
+    fn run_filter(prog: &[libc::sock_filter], packet: &[u8]) -> u32 {
+        let load = |offset: u32, size: u32| {
+            let start = usize::try_from(offset).unwrap();
+            let len = if size == libc::BPF_W { 4 } else { 1 };
+            let bytes = packet.get(start..start + len)?;
+            Some(bytes.iter().fold(0, |acc, b| acc << 8 | u32::from(*b)))
+        };
+        let (mut a, mut x) = (0u32, 0u32);
+        let mut pc = 0;
+        loop {
+            let insn = prog[pc];
+            let code = u32::from(insn.code);
+            pc += 1;
+            match (code & 0x07, code & 0xe0) {
+                (libc::BPF_LD, libc::BPF_ABS) => match load(insn.k, code & 0x18) {
+                    Some(v) => a = v,
+                    None => return 0,
+                },
+                (libc::BPF_LD, libc::BPF_IND) => match load(x + insn.k, code & 0x18) {
+                    Some(v) => a = v,
+                    None => return 0,
+                },
+                (libc::BPF_LDX, libc::BPF_IMM) => x = insn.k,
+                (libc::BPF_JMP, _) if code & 0xf0 == libc::BPF_JA => {
+                    pc += usize::try_from(insn.k).unwrap()
+                }
+                (libc::BPF_JMP, _) if code & 0xf0 == libc::BPF_JEQ => {
+                    pc += usize::from(if a == insn.k { insn.jt } else { insn.jf })
+                }
+                (libc::BPF_RET, _) => return insn.k,
+                _ => panic!("unexpected instruction {:#x}", code),
+            }
+        }
+    }
+
+    fn kernel_message(action: &str, devpath: &str, subsystem: &str) -> Vec<u8> {
+        format!(
+            "{action}@{devpath}\0ACTION={action}\0DEVPATH={devpath}\0SUBSYSTEM={subsystem}\0SEQNUM=42\0"
+        )
+        .into_bytes()
+    }
+
+    fn udev_message(subsystem: &str, magic: u32) -> Vec<u8> {
+        let properties = format!("ACTION=change\0SUBSYSTEM={}\0", subsystem).into_bytes();
+        let mut buf = UDEV_PREFIX.to_vec();
+        buf.extend_from_slice(&magic.to_be_bytes());
+        buf.extend_from_slice(&(UDEV_HEADER_LEN as u32).to_ne_bytes());
+        buf.extend_from_slice(&(UDEV_HEADER_LEN as u32).to_ne_bytes());
+        buf.extend_from_slice(&(properties.len() as u32).to_ne_bytes());
+        buf.extend_from_slice(&udev_hash(subsystem).to_be_bytes());
+        buf.resize(UDEV_HEADER_LEN, 0);
+        buf.extend_from_slice(&properties);
+        buf
+    }
+
+    #[test]
+    fn filter_kernel_messages() {
+        let prog = build_filter(&["backlight", "power_supply"]).unwrap();
+        let devpath = "/devices/LNXSYSTM:00/LNXSYBUS:00/PNP0C0A:00/power_supply/BAT0";
+        let battery = kernel_message("change", devpath, "power_supply");
+        assert_eq!(run_filter(&prog, &battery), FILTER_ACCEPT);
+        let backlight = kernel_message("change", "/devices/intel_backlight", "backlight");
+        assert_eq!(run_filter(&prog, &backlight), FILTER_ACCEPT);
+        // The subsystem must match entirely.
+        let prefix = kernel_message("add", "/devices/foo", "power_supply_ext");
+        assert_eq!(run_filter(&prog, &prefix), 0);
+        let other = kernel_message("add", "/devices/virtual/net/lo", "net");
+        assert_eq!(run_filter(&prog, &other), 0);
+        // A header too long to search is left to user space.
+        let long_path = format!("/devices/{}", "x".repeat(600));
+        let long = kernel_message("add", &long_path, "net");
+        assert_eq!(run_filter(&prog, &long), FILTER_ACCEPT);
+
+        // Nothing is accepted without subsystems.
+        let prog = build_filter(&[]).unwrap();
+        assert_eq!(run_filter(&prog, &battery), 0);
+    }
+
+    #[test]
+    fn filter_udev_messages() {
+        let prog = build_filter(&["backlight", "power_supply"]).unwrap();
+        let battery = udev_message("power_supply", UDEV_MAGIC);
+        assert_eq!(run_filter(&prog, &battery), FILTER_ACCEPT);
+        assert_eq!(run_filter(&prog, &udev_message("net", UDEV_MAGIC)), 0);
+        assert_eq!(run_filter(&prog, &udev_message("power_supply", 0)), 0);
+        // The filtered message can still be parsed.
+        let event = parse_message(&battery).unwrap();
+        assert_eq!(event.vars["SUBSYSTEM"], "power_supply");
+    }
+
+    #[test]
+    fn kernel_accepts_filter() {
+        let socket = match Socket::open_and_bind(Group::Kernel, true, true) {
+            Ok(s) => s,
+            // Some sandboxes don't allow uevent sockets.
+            Err(err) => return eprintln!("skipping: {err}"),
+        };
+        let prog = build_filter(&["backlight", "power_supply", "sound"]).unwrap();
+        socket.attach_filter(&prog).unwrap();
+        // Replacing the filter works too.
+        socket.attach_filter(&build_filter(&[]).unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+use std::{fs, io, iter};
+
+use time::format_description;
+use time::OffsetDateTime;
+
+use crate::module::{Block, ClickEvent, Module};
+use crate::timechange::clock_gettime;
+
+/// Returns the time since boot, including the time spent suspended.
+fn read_uptime() -> io::Result<Duration> {
+    match clock_gettime(libc::CLOCK_BOOTTIME) {
+        Ok(uptime) => Ok(uptime),
+        // The boot clock is missing before Linux 2.6.39.
+        Err(_) => {
+            let s = fs::read_to_string("/proc/uptime")?;
+            s.split_whitespace()
+                .next()
+                .and_then(|secs| secs.parse().ok())
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid /proc/uptime"))
+        }
+    }
+}
+
+/// Formats the uptime with its two largest units, such as `3d 4h`, `5h 12m` or `42m`. Returns
+/// the text and the unit of the last part.
+fn format_uptime(uptime: Duration) -> (String, Duration) {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    let secs = uptime.as_secs();
+    if secs >= DAY {
+        let text = format!("{}d {}h", secs / DAY, secs % DAY / HOUR);
+        (text, Duration::from_secs(HOUR))
+    } else if secs >= HOUR {
+        let text = format!("{}h {}m", secs / HOUR, secs % HOUR / MINUTE);
+        (text, Duration::from_secs(MINUTE))
+    } else {
+        (format!("{}m", secs / MINUTE), Duration::from_secs(MINUTE))
+    }
+}
+
+/// Shows how long the system has been running. A click shows when it booted instead, until the
+/// next click.
+pub(crate) struct Uptime {
+    uptime: Duration,
+    is_boot_shown: bool,
+    timeout: Instant,
+}
+
+impl Uptime {
+    const BOOT_FORMAT: &'static str =
+        "[weekday repr:short] [day padding:none] [month repr:short] [hour]:[minute]";
+
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut r = Self {
+            uptime: Duration::ZERO,
+            is_boot_shown: false,
+            timeout: Instant::now(),
+        };
+        r.read()?;
+        Ok(r)
+    }
+
+    /// Reads the uptime, and schedules the next update when the shown text changes.
+    fn read(&mut self) -> io::Result<()> {
+        self.uptime = read_uptime()?;
+        let (_, unit) = format_uptime(self.uptime);
+        let elapsed = Duration::new(
+            self.uptime.as_secs() % unit.as_secs(),
+            self.uptime.subsec_nanos(),
+        );
+        self.timeout = Instant::now() + (unit - elapsed);
+        Ok(())
+    }
+
+    fn boot_time(&self) -> String {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let boot = now - self.uptime;
+        // The format is valid.
+        let items = format_description::parse(Uptime::BOOT_FORMAT).unwrap();
+        boot.format(&items).unwrap_or_default()
+    }
+}
+
+impl Module for Uptime {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let (uptime, _) = format_uptime(self.uptime);
+        let text = if self.is_boot_shown {
+            format!("Booted {}", self.boot_time())
+        } else {
+            format!("Up {}", uptime)
+        };
+        let block = Block {
+            text,
+            short_text: Some(uptime),
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        let old = format_uptime(self.uptime).0;
+        if let Err(err) = self.read() {
+            eprintln!("failed to read the uptime: {err}");
+            self.timeout = Instant::now() + Duration::from_secs(60);
+            return false;
+        }
+        old != format_uptime(self.uptime).0
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        Some(self.timeout)
+    }
+
+    fn time_changed(&mut self) -> bool {
+        // The monotonic deadline didn't move while the system was suspended, but the uptime
+        // did, and the boot time depends on the clock.
+        self.update();
+        true
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        if event.button != 1 {
+            return false;
+        }
+        self.is_boot_shown = !self.is_boot_shown;
+        true
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+use std::{iter, mem};
+
+use crate::module::{self, Block, ClickEvent, Module};
+
+// See include/uapi/sound/asound.h in the Linux source tree.
+const SNDRV_CTL_ELEM_IFACE_MIXER: libc::c_int = 2;
+const SNDRV_CTL_ELEM_TYPE_BOOLEAN: libc::c_int = 1;
+const SNDRV_CTL_ELEM_TYPE_INTEGER: libc::c_int = 2;
+const SNDRV_CTL_EVENT_SIZE: usize = 64;
+
+#[repr(C)]
+struct ElemId {
+    numid: libc::c_uint,
+    iface: libc::c_int,
+    device: libc::c_uint,
+    subdevice: libc::c_uint,
+    name: [u8; 44],
+    index: libc::c_uint,
+}
+
+#[repr(C)]
+union ElemInfoValue {
+    integer: [libc::c_long; 3],
+    integer64: [libc::c_longlong; 3],
+    reserved: [u8; 128],
+}
+
+#[repr(C)]
+struct ElemInfo {
+    id: ElemId,
+    type_: libc::c_int,
+    access: libc::c_uint,
+    count: libc::c_uint,
+    owner: libc::pid_t,
+    value: ElemInfoValue,
+    reserved: [u8; 64],
+}
+
+#[repr(C)]
+union ElemValueValue {
+    integer: [libc::c_long; 128],
+    integer64: [libc::c_longlong; 64],
+    bytes: [u8; 512],
+}
+
+#[repr(C)]
+struct ElemValue {
+    id: ElemId,
+    indirect: libc::c_uint,
+    value: ElemValueValue,
+    reserved: [u8; 128],
+}
+
+const fn ioc(dir: u32, nr: u32, size: usize) -> libc::c_ulong {
+    ((dir << 30) | ((size as u32) << 16) | ((b'U' as u32) << 8) | nr) as libc::c_ulong
+}
+
+const IOC_READ_WRITE: u32 = 3;
+const SNDRV_CTL_IOCTL_ELEM_INFO: libc::c_ulong =
+    ioc(IOC_READ_WRITE, 0x11, mem::size_of::<ElemInfo>());
+const SNDRV_CTL_IOCTL_ELEM_READ: libc::c_ulong =
+    ioc(IOC_READ_WRITE, 0x12, mem::size_of::<ElemValue>());
+const SNDRV_CTL_IOCTL_ELEM_WRITE: libc::c_ulong =
+    ioc(IOC_READ_WRITE, 0x13, mem::size_of::<ElemValue>());
+const SNDRV_CTL_IOCTL_SUBSCRIBE_EVENTS: libc::c_ulong =
+    ioc(IOC_READ_WRITE, 0x16, mem::size_of::<libc::c_int>());
+
+fn ioctl<T>(file: &File, request: libc::c_ulong, arg: &mut T) -> io::Result<()> {
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request, arg as *mut T) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A mixer control element with integer values, one per channel.
+struct Control {
+    numid: libc::c_uint,
+    count: usize,
+    min: libc::c_long,
+    max: libc::c_long,
+}
+
+impl Control {
+    fn find(file: &File, name: &str, type_: libc::c_int) -> io::Result<Self> {
+        let mut info: ElemInfo = unsafe { mem::zeroed() };
+        info.id.iface = SNDRV_CTL_ELEM_IFACE_MIXER;
+        // Keep a NUL byte at the end.
+        let len = name.len().min(info.id.name.len() - 1);
+        info.id.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ioctl(file, SNDRV_CTL_IOCTL_ELEM_INFO, &mut info)?;
+        if info.type_ != type_ {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected mixer control type",
+            ));
+        }
+        let (min, max) = if type_ == SNDRV_CTL_ELEM_TYPE_INTEGER {
+            let integer = unsafe { info.value.integer };
+            (integer[0], integer[1])
+        } else {
+            (0, 1)
+        };
+        Ok(Self {
+            numid: info.id.numid,
+            count: (info.count as usize).min(128),
+            min,
+            max,
+        })
+    }
+
+    fn read(&self, file: &File) -> io::Result<Vec<libc::c_long>> {
+        let mut value: ElemValue = unsafe { mem::zeroed() };
+        value.id.numid = self.numid;
+        ioctl(file, SNDRV_CTL_IOCTL_ELEM_READ, &mut value)?;
+        Ok(unsafe { value.value.integer[..self.count].to_vec() })
+    }
+
+    /// Sets all of the channels to the same value.
+    fn write(&self, file: &File, v: libc::c_long) -> io::Result<()> {
+        let mut value: ElemValue = unsafe { mem::zeroed() };
+        value.id.numid = self.numid;
+        let v = v.clamp(self.min, self.max);
+        unsafe { value.value.integer[..self.count].fill(v) };
+        ioctl(file, SNDRV_CTL_IOCTL_ELEM_WRITE, &mut value)
+    }
+}
+
+pub(crate) struct Volume {
+    file: File,
+    volume: Control,
+    switch: Option<Control>,
+    /// The average of the channel values.
+    value: libc::c_long,
+    is_muted: bool,
+}
+
+impl Volume {
+    /// The change in volume when scrolling, in percent.
+    const STEP: libc::c_long = 5;
+
+    /// Opens the control device of the first sound card that has a master volume.
+    pub(crate) fn open() -> io::Result<Self> {
+        let mut last_err = module::no_device("no sound card with a master volume found");
+        for card in 0..8 {
+            match Volume::open_card(card) {
+                Ok(v) => return Ok(v),
+                // The card or its master volume doesn't exist.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn open_card(card: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(format!("/dev/snd/controlC{}", card))?;
+        let volume = Control::find(&file, "Master Playback Volume", SNDRV_CTL_ELEM_TYPE_INTEGER)?;
+        // Not all cards can mute the master output.
+        let switch =
+            Control::find(&file, "Master Playback Switch", SNDRV_CTL_ELEM_TYPE_BOOLEAN).ok();
+        ioctl(&file, SNDRV_CTL_IOCTL_SUBSCRIBE_EVENTS, &mut 1)?;
+        let mut r = Self {
+            file,
+            volume,
+            switch,
+            value: 0,
+            is_muted: false,
+        };
+        r.read_state()?;
+        Ok(r)
+    }
+
+    /// Reads the volume and mute state. Returns `true` if they changed.
+    fn read_state(&mut self) -> io::Result<bool> {
+        let values = self.volume.read(&self.file)?;
+        let value = values.iter().sum::<libc::c_long>() / (values.len().max(1) as libc::c_long);
+        // The switch is on when the sound is not muted.
+        let is_muted = match &self.switch {
+            Some(switch) => switch.read(&self.file)?.iter().all(|v| *v == 0),
+            None => false,
+        };
+        let dirty = value != self.value || is_muted != self.is_muted;
+        self.value = value;
+        self.is_muted = is_muted;
+        Ok(dirty)
+    }
+
+    fn percentage(&self) -> libc::c_long {
+        let range = (self.volume.max - self.volume.min).max(1);
+        ((self.value - self.volume.min) * 100 + range / 2) / range
+    }
+
+    fn set_percentage(&self, percentage: libc::c_long) -> io::Result<()> {
+        let range = self.volume.max - self.volume.min;
+        // Round to the nearest value so that small steps are not lost.
+        let value = self.volume.min + (percentage * range + 50) / 100;
+        self.volume.write(&self.file, value)
+    }
+}
+
+impl Module for Volume {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let text = if self.is_muted {
+            format!("Vol: {}% (muted)", self.percentage())
+        } else {
+            format!("Vol: {}%", self.percentage())
+        };
+        let block = Block {
+            text,
+            is_warning: false,
+            ..Default::default()
+        };
+        Box::new(iter::once(block))
+    }
+
+    fn update(&mut self) -> bool {
+        // Drain the events. Their content doesn't matter as the state is read again.
+        let mut buf = [0u8; SNDRV_CTL_EVENT_SIZE * 16];
+        loop {
+            match (&self.file).read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) => {
+                    if err.kind() != io::ErrorKind::WouldBlock {
+                        eprintln!("failed to read sound control events: {err}");
+                    }
+                    break;
+                }
+            }
+        }
+        match self.read_state() {
+            Ok(dirty) => dirty,
+            Err(err) => {
+                eprintln!("failed to read volume: {err}");
+                false
+            }
+        }
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.file.as_raw_fd())
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        None
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        let ret = match event.button {
+            2 => match &self.switch {
+                Some(switch) => switch.write(&self.file, self.is_muted.into()),
+                None => return false,
+            },
+            4 => self.set_percentage(self.percentage() + Volume::STEP),
+            5 => self.set_percentage(self.percentage() - Volume::STEP),
+            _ => return false,
+        };
+        if let Err(err) = ret {
+            eprintln!("failed to change volume: {err}");
+        }
+        self.update()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<bool> {
+        match key {
+            "volume" => {
+                let percentage = value
+                    .trim_end_matches('%')
+                    .parse()
+                    .map_err(|_| module::invalid_value(key, value))?;
+                self.set_percentage(percentage)?;
+            }
+            "muted" => {
+                let is_muted = match value {
+                    "true" => true,
+                    "false" => false,
+                    "toggle" => !self.is_muted,
+                    _ => return Err(module::invalid_value(key, value)),
+                };
+                match &self.switch {
+                    Some(switch) => switch.write(&self.file, (!is_muted).into())?,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "the sound card can't be muted",
+                        ))
+                    }
+                }
+            }
+            _ => return Err(module::unknown_option(key)),
+        }
+        Ok(self.update())
+    }
+}
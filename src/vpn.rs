@@ -0,0 +1,409 @@
+use std::convert::TryInto;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::module::{Block, Module};
+use crate::netlink;
+
+// See include/uapi/linux/rtnetlink.h in the Linux source tree.
+const RTNLGRP_LINK: u32 = 1;
+const IFINFOMSG_LEN: usize = 16;
+
+// See include/uapi/linux/wireguard.h in the Linux source tree.
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PEERS: u16 = 8;
+const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+
+#[derive(PartialEq)]
+enum Kind {
+    WireGuard,
+    Tun,
+}
+
+#[derive(PartialEq)]
+struct Tunnel {
+    name: String,
+    kind: Kind,
+    is_up: bool,
+    /// The most recent handshake with any peer, only for WireGuard tunnels.
+    last_handshake: Option<SystemTime>,
+}
+
+impl Tunnel {
+    /// Returns `None` for links that are not tunnels.
+    fn from_message(msg: &netlink::Message) -> Option<Self> {
+        let header = msg.payload.get(..IFINFOMSG_LEN)?;
+        let flags = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+        let mut name = None;
+        let mut kind = None;
+        for attr in netlink::Attrs::new(&msg.payload[IFINFOMSG_LEN..]) {
+            match attr.kind {
+                libc::IFLA_IFNAME => name = attr.str(),
+                libc::IFLA_LINKINFO => {
+                    kind = attr
+                        .nested()
+                        .find(|a| a.kind == libc::IFLA_INFO_KIND)
+                        .and_then(|a| match a.str() {
+                            Some("wireguard") => Some(Kind::WireGuard),
+                            Some("tun") => Some(Kind::Tun),
+                            _ => None,
+                        })
+                }
+                _ => {}
+            }
+        }
+        let up_flags = (libc::IFF_UP | libc::IFF_LOWER_UP) as u32;
+        let is_up = (flags & up_flags) == up_flags;
+        Some(Self {
+            name: name?.to_owned(),
+            kind: kind?,
+            is_up,
+            last_handshake: None,
+        })
+    }
+}
+
+/// Finds the most recent handshake in a WireGuard device dump.
+fn parse_last_handshake(replies: &[netlink::Message]) -> Option<SystemTime> {
+    let mut last = None;
+    for (_, attrs) in replies.iter().filter_map(|m| m.genl()) {
+        for attr in attrs.filter(|a| a.kind == WGDEVICE_A_PEERS) {
+            for peer in attr.nested() {
+                let time = match peer
+                    .nested()
+                    .find(|a| a.kind == WGPEER_A_LAST_HANDSHAKE_TIME)
+                {
+                    Some(a) => a,
+                    None => continue,
+                };
+                // This is a struct __kernel_timespec.
+                let secs = match time.payload.get(..8) {
+                    Some(b) => i64::from_ne_bytes(b.try_into().unwrap()),
+                    None => continue,
+                };
+                // Zero means that there was no handshake yet.
+                if secs <= 0 {
+                    continue;
+                }
+                let t = UNIX_EPOCH + Duration::from_secs(secs as u64);
+                if last.map(|l| t > l).unwrap_or(true) {
+                    last = Some(t);
+                }
+            }
+        }
+    }
+    last
+}
+
+pub(crate) struct Vpn {
+    /// Used for requests.
+    socket: netlink::Socket,
+    /// Receives link notifications.
+    events: netlink::Socket,
+    /// Used for WireGuard requests, opened when the first WireGuard tunnel is seen.
+    wireguard: Option<(netlink::Socket, u16)>,
+    /// Whether WireGuard refused to describe its devices. Only privileged users may do it, so the
+    /// handshakes are not queried anymore.
+    is_wireguard_denied: bool,
+    timeout: Instant,
+    tunnels: Vec<Tunnel>,
+}
+
+impl Vpn {
+    /// Handshakes are not notified, so they must be refreshed periodically.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// WireGuard refuses to use a session older than this (`REJECT_AFTER_TIME`), so the tunnel
+    /// doesn't work anymore if there was no handshake since.
+    const STALE_HANDSHAKE: Duration = Duration::from_secs(180);
+
+    pub(crate) fn new() -> io::Result<Self> {
+        let socket = netlink::Socket::open(libc::NETLINK_ROUTE, false, true)?;
+        let events = netlink::Socket::open(libc::NETLINK_ROUTE, true, true)?;
+        events.add_membership(RTNLGRP_LINK)?;
+        let mut vpn = Self {
+            socket,
+            events,
+            wireguard: None,
+            is_wireguard_denied: false,
+            timeout: Instant::now() + Vpn::TIMEOUT,
+            tunnels: Vec::new(),
+        };
+        vpn.tunnels = vpn.query_tunnels()?;
+        Ok(vpn)
+    }
+
+    fn query_tunnels(&mut self) -> io::Result<Vec<Tunnel>> {
+        let mut msg = netlink::MessageBuilder::new(libc::RTM_GETLINK, libc::NLM_F_DUMP as u16);
+        msg.push(&[0u8; IFINFOMSG_LEN]);
+        let mut tunnels: Vec<Tunnel> = self
+            .socket
+            .request(msg)?
+            .iter()
+            .filter(|m| m.kind == libc::RTM_NEWLINK)
+            .filter_map(Tunnel::from_message)
+            .collect();
+        for tunnel in tunnels.iter_mut() {
+            if tunnel.kind != Kind::WireGuard || !tunnel.is_up || self.is_wireguard_denied {
+                continue;
+            }
+            match self.query_last_handshake(&tunnel.name) {
+                Ok(t) => tunnel.last_handshake = t,
+                Err(err) if err.raw_os_error() == Some(libc::EPERM) => {
+                    eprintln!("not allowed to query WireGuard devices, hiding the handshakes");
+                    self.is_wireguard_denied = true;
+                    self.wireguard = None;
+                }
+                Err(err) => eprintln!("failed to query WireGuard device {}: {}", tunnel.name, err),
+            }
+        }
+        Ok(tunnels)
+    }
+
+    fn query_last_handshake(&mut self, name: &str) -> io::Result<Option<SystemTime>> {
+        if self.wireguard.is_none() {
+            let mut socket = netlink::Socket::open(libc::NETLINK_GENERIC, false, true)?;
+            let family = netlink::Family::resolve(&mut socket, "wireguard")?;
+            self.wireguard = Some((socket, family.id));
+        }
+        let (socket, family) = self.wireguard.as_mut().unwrap();
+
+        let mut name_z = name.as_bytes().to_vec();
+        name_z.push(b'\0');
+        let mut msg =
+            netlink::MessageBuilder::genl(*family, WG_CMD_GET_DEVICE, libc::NLM_F_DUMP as u16);
+        msg.push_attr(WGDEVICE_A_IFNAME, &name_z);
+        Ok(parse_last_handshake(&socket.request(msg)?))
+    }
+}
+
+impl Module for Vpn {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        let now = SystemTime::now();
+        Box::new(self.tunnels.iter().map(move |tunnel| {
+            if !tunnel.is_up {
+                return Block {
+                    text: format!("VPN {}: down", tunnel.name),
+                    is_warning: false,
+                    ..Default::default()
+                };
+            }
+            // The handshake is unknown without the permission to query it, which isn't a problem
+            // of the tunnel.
+            if tunnel.kind != Kind::WireGuard || self.is_wireguard_denied {
+                return Block {
+                    text: format!("VPN {}: up", tunnel.name),
+                    is_warning: false,
+                    ..Default::default()
+                };
+            }
+            match tunnel.last_handshake {
+                Some(t) => {
+                    let age = now.duration_since(t).unwrap_or_default();
+                    Block {
+                        text: format!("VPN {}: up ({} ago)", tunnel.name, format_age(age)),
+                        is_warning: age >= Vpn::STALE_HANDSHAKE,
+                        ..Default::default()
+                    }
+                }
+                None => Block {
+                    text: format!("VPN {}: up (no handshake)", tunnel.name),
+                    is_warning: true,
+                    ..Default::default()
+                },
+            }
+        }))
+    }
+
+    fn update(&mut self) -> bool {
+        // Drain the notifications. Their content doesn't matter as everything is queried again.
+        loop {
+            if let Err(err) = self.events.recv() {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    eprintln!("failed to read link notification: {err}");
+                }
+                break;
+            }
+        }
+        self.timeout = Instant::now() + Vpn::TIMEOUT;
+        match self.query_tunnels() {
+            Ok(tunnels) => {
+                // The handshake age is displayed, so WireGuard tunnels always need to be rendered
+                // again.
+                let dirty =
+                    tunnels != self.tunnels || tunnels.iter().any(|t| t.last_handshake.is_some());
+                self.tunnels = tunnels;
+                return dirty;
+            }
+            Err(err) => eprintln!("failed to query tunnels: {err}"),
+        }
+        false
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.events.as_raw_fd())
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        Some(self.timeout)
+    }
+}
+
+/// Formats a duration with its largest unit only, such as `42s`, `3m` or `2h`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m", secs / 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{}h", secs / (60 * 60))
+    } else {
+        format!("{}d", secs / (24 * 60 * 60))
+    }
+}
+
+// The fixtures are in the byte order of the machine they were recorded on.
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    /// The reply to an `RTM_GETLINK` dump: a WireGuard tunnel that is up, a tun device without
+    /// carrier, an Ethernet interface, a veth interface, and the end of the dump.
+    const LINKS: &[u8] = &[
+        // nlmsghdr: length 68, RTM_NEWLINK, NLM_F_MULTI, sequence 1, port ID.
+        0x44, 0x00, 0x00, 0x00, 0x10, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // ifinfomsg: ARPHRD_NONE, index 5, IFF_UP | IFF_POINTOPOINT | IFF_RUNNING | IFF_NOARP |
+        // IFF_LOWER_UP.
+        0x00, 0x00, 0xfe, 0xff, 0x05, 0x00, 0x00, 0x00, 0xd1, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, //
+        // IFLA_IFNAME: "wg0".
+        0x08, 0x00, 0x03, 0x00, b'w', b'g', b'0', 0x00, //
+        // IFLA_MTU: 1420, which is ignored.
+        0x08, 0x00, 0x04, 0x00, 0x8c, 0x05, 0x00, 0x00, //
+        // IFLA_LINKINFO, nested.
+        0x14, 0x00, 0x12, 0x00, //
+        // IFLA_INFO_KIND: "wireguard".
+        0x0e, 0x00, 0x01, 0x00, b'w', b'i', b'r', b'e', b'g', b'u', b'a', b'r', b'd', 0x00, 0x00,
+        0x00, //
+        // nlmsghdr: length 68.
+        0x44, 0x00, 0x00, 0x00, 0x10, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // ifinfomsg: ARPHRD_NONE, index 6, IFF_UP | IFF_POINTOPOINT | IFF_NOARP | IFF_MULTICAST, but
+        // not IFF_LOWER_UP as no program has the device open.
+        0x00, 0x00, 0xfe, 0xff, 0x06, 0x00, 0x00, 0x00, 0x91, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, //
+        // IFLA_IFNAME: "tun0".
+        0x09, 0x00, 0x03, 0x00, b't', b'u', b'n', b'0', 0x00, 0x00, 0x00, 0x00, //
+        // IFLA_LINKINFO, nested.
+        0x18, 0x00, 0x12, 0x00, //
+        // IFLA_INFO_KIND: "tun".
+        0x08, 0x00, 0x01, 0x00, b't', b'u', b'n', 0x00, //
+        // IFLA_INFO_DATA, nested, which is ignored.
+        0x0c, 0x00, 0x02, 0x80, 0x05, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 44.
+        0x2c, 0x00, 0x00, 0x00, 0x10, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // ifinfomsg: ARPHRD_ETHER, index 2, IFF_UP | IFF_BROADCAST | IFF_RUNNING | IFF_MULTICAST |
+        // IFF_LOWER_UP.
+        0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x43, 0x10, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, //
+        // IFLA_IFNAME: "eth0", without IFLA_LINKINFO.
+        0x09, 0x00, 0x03, 0x00, b'e', b't', b'h', b'0', 0x00, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 60.
+        0x3c, 0x00, 0x00, 0x00, 0x10, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // ifinfomsg: ARPHRD_ETHER, index 7, IFF_UP | IFF_BROADCAST | IFF_RUNNING | IFF_MULTICAST |
+        // IFF_LOWER_UP.
+        0x00, 0x00, 0x01, 0x00, 0x07, 0x00, 0x00, 0x00, 0x43, 0x10, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, //
+        // IFLA_IFNAME: "veth0".
+        0x0a, 0x00, 0x03, 0x00, b'v', b'e', b't', b'h', b'0', 0x00, 0x00, 0x00, //
+        // IFLA_LINKINFO, nested.
+        0x10, 0x00, 0x12, 0x00, //
+        // IFLA_INFO_KIND: "veth".
+        0x09, 0x00, 0x01, 0x00, b'v', b'e', b't', b'h', 0x00, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 20, NLMSG_DONE, followed by an error code of 0.
+        0x14, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// The reply to a `WG_CMD_GET_DEVICE` dump of a device with three peers.
+    const DEVICE: &[u8] = &[
+        // nlmsghdr: length 148, family 0x1c, NLM_F_MULTI, sequence 2, port ID.
+        0x94, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // genlmsghdr: WG_CMD_GET_DEVICE, version 1.
+        0x00, 0x01, 0x00, 0x00, //
+        // WGDEVICE_A_IFINDEX: 5.
+        0x08, 0x00, 0x01, 0x00, 0x05, 0x00, 0x00, 0x00, //
+        // WGDEVICE_A_IFNAME: "wg0".
+        0x08, 0x00, 0x02, 0x00, b'w', b'g', b'0', 0x00, //
+        // WGDEVICE_A_PEERS, nested.
+        0x70, 0x00, 0x08, 0x80, //
+        // Peer 0, nested, with a handshake at 1700000000.000000123.
+        0x24, 0x00, 0x00, 0x80, //
+        // WGPEER_A_LAST_HANDSHAKE_TIME: struct __kernel_timespec.
+        0x14, 0x00, 0x06, 0x00, 0x00, 0xf1, 0x53, 0x65, 0x00, 0x00, 0x00, 0x00, 0x7b, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, //
+        // WGPEER_A_RX_BYTES, which is ignored.
+        0x0c, 0x00, 0x07, 0x00, 0xd2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        // Peer 1, nested, with a more recent handshake at 1700000100.000000456.
+        0x24, 0x00, 0x01, 0x80, //
+        // WGPEER_A_LAST_HANDSHAKE_TIME: struct __kernel_timespec.
+        0x14, 0x00, 0x06, 0x00, 0x64, 0xf1, 0x53, 0x65, 0x00, 0x00, 0x00, 0x00, 0xc8, 0x01, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, //
+        // WGPEER_A_RX_BYTES, which is ignored.
+        0x0c, 0x00, 0x07, 0x00, 0xd2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        // Peer 2, nested, with no handshake yet.
+        0x24, 0x00, 0x02, 0x80, //
+        // WGPEER_A_LAST_HANDSHAKE_TIME: struct __kernel_timespec.
+        0x14, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, //
+        // WGPEER_A_RX_BYTES, which is ignored.
+        0x0c, 0x00, 0x07, 0x00, 0xd2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 20, NLMSG_DONE, followed by an error code of 0.
+        0x14, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn parse_link_dump() {
+        let messages = netlink::parse_messages(LINKS).unwrap();
+        assert_eq!(messages.len(), 5);
+        let tunnels: Vec<Tunnel> = messages
+            .iter()
+            .filter(|m| m.kind == libc::RTM_NEWLINK)
+            .filter_map(Tunnel::from_message)
+            .collect();
+        assert_eq!(tunnels.len(), 2, "only wireguard and tun links are tunnels");
+
+        assert_eq!(tunnels[0].name, "wg0");
+        assert!(tunnels[0].kind == Kind::WireGuard);
+        assert!(tunnels[0].is_up);
+        assert_eq!(tunnels[0].last_handshake, None);
+
+        assert_eq!(tunnels[1].name, "tun0");
+        assert!(tunnels[1].kind == Kind::Tun);
+        assert!(
+            !tunnels[1].is_up,
+            "a tun device without IFF_LOWER_UP is down"
+        );
+    }
+
+    #[test]
+    fn parse_device_dump() {
+        let messages = netlink::parse_messages(DEVICE).unwrap();
+        assert_eq!(
+            parse_last_handshake(&messages),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_100))
+        );
+        // The end of the dump alone has no handshake.
+        assert_eq!(parse_last_handshake(&messages[1..]), None);
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Child, ChildStdin, ChildStdout, Stdio};
+use std::time::Instant;
+
+use crate::command::set_non_blocking;
+use crate::config;
+use crate::json;
+use crate::module::{Block, ClickEvent, Module};
+use crate::signal;
+
+fn parse_block(value: &json::Value) -> Option<Block> {
+    let field = |key| {
+        value
+            .get(key)
+            .and_then(json::Value::as_str)
+            .map(str::to_owned)
+    };
+    Some(Block {
+        text: field("full_text")?,
+        short_text: field("short_text"),
+        color: field("color"),
+        is_urgent: value
+            .get("urgent")
+            .and_then(json::Value::as_bool)
+            .unwrap_or(false),
+        ..Default::default()
+    })
+}
+
+/// Parses a line printed by a widget: an array of blocks, or a single block. Returns `None` if it
+/// is invalid.
+fn parse_blocks(line: &str) -> Option<Vec<Block>> {
+    let value = json::parse(line).ok()?;
+    match value.as_array() {
+        Some(values) => values.iter().map(parse_block).collect(),
+        None => Some(vec![parse_block(&value)?]),
+    }
+}
+
+/// Runs a program that prints its blocks as JSON lines and receives the clicks on them. See the
+/// README for the protocol. The program is started again if it exits.
+pub(crate) struct Widget {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    /// The incomplete line.
+    buf: Vec<u8>,
+    blocks: Vec<Block>,
+}
+
+impl Widget {
+    /// Creates the module of a `[widget:<name>]` section and starts the program.
+    pub(crate) fn new(name: &str, config: &config::Section) -> io::Result<Self> {
+        let command = config
+            .get("command")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing command option"))?;
+        let mut command_builder = process::Command::new("/bin/sh");
+        command_builder
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .env("BLOCK_NAME", name);
+        unsafe { command_builder.pre_exec(signal::unblock_all) };
+        let mut child = command_builder.spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        // Construct the module first so that the program is stopped if there is an error.
+        let r = Self {
+            name: name.to_owned(),
+            child,
+            stdin,
+            stdout,
+            buf: Vec::new(),
+            blocks: Vec::new(),
+        };
+        set_non_blocking(r.stdin.as_raw_fd())?;
+        set_non_blocking(r.stdout.as_raw_fd())?;
+        Ok(r)
+    }
+
+    /// Reads the lines that the program printed. Returns `true` if the blocks changed, and
+    /// whether the program closed its output.
+    fn read(&mut self) -> (bool, bool) {
+        let mut chunk = [0u8; 4096];
+        let is_eof = loop {
+            match self.stdout.read(&mut chunk) {
+                Ok(0) => break true,
+                Ok(read) => self.buf.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break false,
+                Err(err) => {
+                    eprintln!("failed to read the output of {}: {err}", self.name);
+                    break true;
+                }
+            }
+        };
+
+        let mut dirty = false;
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse_blocks(line) {
+                Some(blocks) => {
+                    dirty |= blocks != self.blocks;
+                    self.blocks = blocks;
+                }
+                None => eprintln!("invalid blocks from {}: {}", self.name, line),
+            }
+        }
+        (dirty, is_eof)
+    }
+}
+
+impl Module for Widget {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        Box::new(self.blocks.iter().cloned())
+    }
+
+    fn update(&mut self) -> bool {
+        // The end of the output is handled in `hangup`.
+        self.read().0
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.stdout.as_raw_fd())
+    }
+
+    fn hangup(&mut self) -> io::Result<bool> {
+        let (dirty, is_eof) = self.read();
+        if is_eof {
+            // The program exited, so it must be started again.
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the program closed its output",
+            ));
+        }
+        Ok(dirty)
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        None
+    }
+
+    fn click(&mut self, event: &ClickEvent) -> bool {
+        let line = format!(
+            "{{\"button\":{},\"block\":{}}}\n",
+            event.button, event.block
+        );
+        // Writes of less than PIPE_BUF bytes are atomic, so a line is never cut.
+        if let Err(err) = self.stdin.write_all(line.as_bytes()) {
+            eprintln!("failed to send a click to {}: {err}", self.name);
+        }
+        false
+    }
+}
+
+impl Drop for Widget {
+    fn drop(&mut self) {
+        // Don't leave a zombie process behind.
+        if let Err(err) = self.child.kill().and_then(|_| self.child.wait()) {
+            eprintln!("failed to stop {}: {err}", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Option<Vec<String>> {
+        Some(parse_blocks(line)?.into_iter().map(|b| b.text).collect())
+    }
+
+    #[test]
+    fn parse_single_block_or_array() {
+        assert_eq!(texts(r#"{"full_text":"CPU 12%"}"#).unwrap(), ["CPU 12%"]);
+        assert_eq!(
+            texts(r#"[{"full_text":"a"},{"full_text":"b"}]"#).unwrap(),
+            ["a", "b"]
+        );
+        // An empty array hides the widget.
+        assert!(texts("[]").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_optional_members() {
+        let blocks = parse_blocks(
+            r##"[{"full_text":"fan","short_text":"f","color":"#ff8000","urgent":true,"x":[1]},
+                {"full_text":"cpu"}]"##,
+        )
+        .unwrap();
+        assert_eq!(blocks[0].short_text.as_deref(), Some("f"));
+        assert_eq!(blocks[0].color.as_deref(), Some("#ff8000"));
+        assert!(blocks[0].is_urgent);
+        assert_eq!(blocks[1].short_text, None);
+        assert_eq!(blocks[1].color, None);
+        assert!(!blocks[1].is_urgent);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // A block without a full_text rejects the whole line.
+        assert!(texts(r#"[{"full_text":"a"},{"short_text":"b"}]"#).is_none());
+        assert!(texts(r#"{"full_text":1}"#).is_none());
+        assert!(texts(r#""full_text""#).is_none());
+        assert!(texts(r#"{"full_text":"a""#).is_none());
+        assert!(texts("CPU 12%").is_none());
+    }
+}
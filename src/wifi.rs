@@ -0,0 +1,441 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use crate::module::{self, Block, Module};
+use crate::netlink;
+
+// See include/uapi/linux/nl80211.h in the Linux source tree.
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_STATION: u8 = 17;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_IFTYPE: u16 = 5;
+const NL80211_ATTR_STA_INFO: u16 = 21;
+const NL80211_ATTR_WIPHY_FREQ: u16 = 38;
+const NL80211_ATTR_SSID: u16 = 52;
+const NL80211_IFTYPE_STATION: u32 = 2;
+const NL80211_STA_INFO_SIGNAL: u16 = 7;
+const NL80211_STA_INFO_TX_BITRATE: u16 = 8;
+const NL80211_RATE_INFO_BITRATE: u16 = 1;
+const NL80211_RATE_INFO_BITRATE32: u16 = 5;
+
+#[derive(PartialEq)]
+struct Station {
+    /// The signal strength, in dBm.
+    signal: Option<i8>,
+    /// The transmit bitrate, in units of 100 kbit/s.
+    bitrate: Option<u32>,
+}
+
+impl Station {
+    fn from_attrs(mut attrs: netlink::Attrs) -> Option<Self> {
+        let info = attrs.find(|a| a.kind == NL80211_ATTR_STA_INFO)?;
+        let mut signal = None;
+        let mut bitrate = None;
+        for attr in info.nested() {
+            match attr.kind {
+                NL80211_STA_INFO_SIGNAL => signal = attr.u8().map(|s| s as i8),
+                NL80211_STA_INFO_TX_BITRATE => {
+                    for rate in attr.nested() {
+                        match rate.kind {
+                            NL80211_RATE_INFO_BITRATE32 => bitrate = rate.u32(),
+                            // Only use the 16-bit value if the 32-bit one is not there.
+                            NL80211_RATE_INFO_BITRATE if bitrate.is_none() => {
+                                bitrate = rate.u16().map(u32::from)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(Self { signal, bitrate })
+    }
+
+    /// Maps the signal strength to a percentage, -100 dBm being 0% and -50 dBm being 100%.
+    fn quality(&self) -> Option<u8> {
+        self.signal
+            .map(|s| (2 * (i16::from(s) + 100)).clamp(0, 100) as u8)
+    }
+}
+
+#[derive(PartialEq)]
+struct Interface {
+    index: u32,
+    name: String,
+    ssid: Option<String>,
+    /// The frequency of the channel, in MHz.
+    frequency: Option<u32>,
+    station: Option<Station>,
+}
+
+impl Interface {
+    /// Returns `None` for interfaces that are not in station (client) mode.
+    fn from_attrs(attrs: netlink::Attrs) -> Option<Self> {
+        let mut index = None;
+        let mut name = None;
+        let mut type_ = None;
+        let mut ssid = None;
+        let mut frequency = None;
+        for attr in attrs {
+            match attr.kind {
+                NL80211_ATTR_IFINDEX => index = attr.u32(),
+                NL80211_ATTR_IFNAME => name = attr.str(),
+                NL80211_ATTR_IFTYPE => type_ = attr.u32(),
+                // The SSID is an arbitrary byte string.
+                NL80211_ATTR_SSID => {
+                    ssid = Some(String::from_utf8_lossy(attr.payload).into_owned())
+                }
+                NL80211_ATTR_WIPHY_FREQ => frequency = attr.u32(),
+                _ => {}
+            }
+        }
+        if type_ != Some(NL80211_IFTYPE_STATION) {
+            return None;
+        }
+        Some(Self {
+            index: index?,
+            name: name?.to_owned(),
+            ssid,
+            frequency,
+            station: None,
+        })
+    }
+}
+
+pub(crate) struct Wifi {
+    /// Used for requests.
+    socket: netlink::Socket,
+    /// Receives nl80211 notifications.
+    events: netlink::Socket,
+    family: u16,
+    timeout: Instant,
+    interfaces: Vec<Interface>,
+}
+
+impl Wifi {
+    /// The signal strength is not notified, so it must be refreshed periodically.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut socket = netlink::Socket::open(libc::NETLINK_GENERIC, false, true)?;
+        let family = match netlink::Family::resolve(&mut socket, "nl80211") {
+            Ok(f) => f,
+            // The family is registered by the first Wi-Fi driver.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(module::no_device("no Wi-Fi driver found"))
+            }
+            Err(err) => return Err(err),
+        };
+        let events = netlink::Socket::open(libc::NETLINK_GENERIC, true, true)?;
+        for name in ["config", "mlme"] {
+            match family.mcast_group(name) {
+                Some(group) => events.add_membership(group)?,
+                None => eprintln!("missing nl80211 multicast group: {}", name),
+            }
+        }
+        let mut wifi = Self {
+            socket,
+            events,
+            family: family.id,
+            timeout: Instant::now() + Wifi::TIMEOUT,
+            interfaces: Vec::new(),
+        };
+        wifi.interfaces = wifi.query_interfaces()?;
+        Ok(wifi)
+    }
+
+    fn query_interfaces(&mut self) -> io::Result<Vec<Interface>> {
+        let msg = netlink::MessageBuilder::genl(
+            self.family,
+            NL80211_CMD_GET_INTERFACE,
+            libc::NLM_F_DUMP as u16,
+        );
+        let mut interfaces: Vec<Interface> = self
+            .socket
+            .request(msg)?
+            .iter()
+            .filter_map(|m| m.genl())
+            .filter_map(|(_, attrs)| Interface::from_attrs(attrs))
+            .collect();
+        for interface in interfaces.iter_mut() {
+            if interface.ssid.is_none() {
+                continue;
+            }
+            match self.query_station(interface.index) {
+                Ok(s) => interface.station = s,
+                Err(err) => eprintln!("failed to query station on {}: {}", interface.name, err),
+            }
+        }
+        Ok(interfaces)
+    }
+
+    /// Returns information about the access point that the interface is connected to.
+    fn query_station(&mut self, index: u32) -> io::Result<Option<Station>> {
+        let mut msg = netlink::MessageBuilder::genl(
+            self.family,
+            NL80211_CMD_GET_STATION,
+            libc::NLM_F_DUMP as u16,
+        );
+        msg.push_attr(NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+        Ok(self
+            .socket
+            .request(msg)?
+            .iter()
+            .filter_map(|m| m.genl())
+            .find_map(|(_, attrs)| Station::from_attrs(attrs)))
+    }
+}
+
+impl Module for Wifi {
+    fn render<'a>(&'a self) -> Box<dyn Iterator<Item = Block> + 'a> {
+        Box::new(self.interfaces.iter().map(|interface| {
+            let ssid = match &interface.ssid {
+                Some(s) => s,
+                None => {
+                    return Block {
+                        text: format!("{}: disconnected", interface.name),
+                        is_warning: false,
+                        ..Default::default()
+                    }
+                }
+            };
+            let mut details = Vec::new();
+            let quality = interface.station.as_ref().and_then(|s| s.quality());
+            if let Some(station) = &interface.station {
+                if let Some(signal) = station.signal {
+                    details.push(format!("{} dBm", signal));
+                }
+                if let Some(bitrate) = station.bitrate {
+                    details.push(format!("{}.{} Mb/s", bitrate / 10, bitrate % 10));
+                }
+            }
+            if let Some(frequency) = interface.frequency {
+                details.push(format!("{} MHz", frequency));
+            }
+            let mut text = format!("{}: {}", interface.name, ssid);
+            if let Some(quality) = quality {
+                text += &format!(" {}%", quality);
+            }
+            if !details.is_empty() {
+                text += &format!(" ({})", details.join(", "));
+            }
+            Block {
+                text,
+                is_warning: quality.map(|q| q <= 20).unwrap_or(false),
+                ..Default::default()
+            }
+        }))
+    }
+
+    fn update(&mut self) -> bool {
+        // Drain the notifications. Their content doesn't matter as everything is queried again.
+        loop {
+            if let Err(err) = self.events.recv() {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    eprintln!("failed to read nl80211 notification: {err}");
+                }
+                break;
+            }
+        }
+        self.timeout = Instant::now() + Wifi::TIMEOUT;
+        match self.query_interfaces() {
+            Ok(interfaces) => {
+                if interfaces != self.interfaces {
+                    self.interfaces = interfaces;
+                    return true;
+                }
+            }
+            Err(err) => eprintln!("failed to query wireless interfaces: {err}"),
+        }
+        false
+    }
+
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.events.as_raw_fd())
+    }
+
+    fn timeout(&self) -> Option<Instant> {
+        Some(self.timeout)
+    }
+}
+
+// The fixtures are in the byte order of the machine they were recorded on.
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    /// The reply to an `NL80211_CMD_GET_INTERFACE` dump: a connected station interface, an access
+    /// point interface, and the end of the dump.
+    const INTERFACES: &[u8] = &[
+        // nlmsghdr: length 76, family 0x22, NLM_F_MULTI, sequence 1, port ID.
+        0x4c, 0x00, 0x00, 0x00, 0x22, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // genlmsghdr: NL80211_CMD_NEW_INTERFACE.
+        0x07, 0x01, 0x00, 0x00, //
+        // NL80211_ATTR_IFINDEX: 3.
+        0x08, 0x00, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_IFNAME: "wlan0".
+        0x0a, 0x00, 0x04, 0x00, b'w', b'l', b'a', b'n', b'0', 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_IFTYPE: NL80211_IFTYPE_STATION.
+        0x08, 0x00, 0x05, 0x00, 0x02, 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_WIPHY_FREQ: 5180.
+        0x08, 0x00, 0x26, 0x00, 0x3c, 0x14, 0x00, 0x00, //
+        // NL80211_ATTR_SSID: "home".
+        0x08, 0x00, 0x34, 0x00, b'h', b'o', b'm', b'e', //
+        // NL80211_ATTR_WDEV, which is ignored.
+        0x0c, 0x00, 0x99, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 44.
+        0x2c, 0x00, 0x00, 0x00, 0x22, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x07, 0x01, 0x00, 0x00, //
+        // NL80211_ATTR_IFINDEX: 4.
+        0x08, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_IFNAME: "ap0".
+        0x08, 0x00, 0x04, 0x00, b'a', b'p', b'0', 0x00, //
+        // NL80211_ATTR_IFTYPE: NL80211_IFTYPE_AP.
+        0x08, 0x00, 0x05, 0x00, 0x03, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 20, NLMSG_DONE, followed by an error code of 0.
+        0x14, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// The reply to an `NL80211_CMD_GET_STATION` dump on a fast link, where the kernel sends both
+    /// the 32-bit and the 16-bit bitrates.
+    const STATION: &[u8] = &[
+        // nlmsghdr: length 88, family 0x22, NLM_F_MULTI, sequence 2, port ID.
+        0x58, 0x00, 0x00, 0x00, 0x22, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        // genlmsghdr: NL80211_CMD_NEW_STATION.
+        0x13, 0x01, 0x00, 0x00, //
+        // NL80211_ATTR_IFINDEX: 3.
+        0x08, 0x00, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_MAC.
+        0x0a, 0x00, 0x06, 0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, 0x00, //
+        // NL80211_ATTR_STA_INFO, nested.
+        0x30, 0x00, 0x15, 0x80, //
+        // NL80211_STA_INFO_INACTIVE_TIME: 16.
+        0x08, 0x00, 0x01, 0x00, 0x10, 0x00, 0x00, 0x00, //
+        // NL80211_STA_INFO_SIGNAL: -52.
+        0x05, 0x00, 0x07, 0x00, 0xcc, 0x00, 0x00, 0x00, //
+        // NL80211_STA_INFO_TX_BITRATE, nested.
+        0x1c, 0x00, 0x08, 0x80, //
+        // NL80211_RATE_INFO_BITRATE32: 8667.
+        0x08, 0x00, 0x05, 0x00, 0xdb, 0x21, 0x00, 0x00, //
+        // NL80211_RATE_INFO_BITRATE: 8667.
+        0x06, 0x00, 0x01, 0x00, 0xdb, 0x21, 0x00, 0x00, //
+        // NL80211_RATE_INFO_MCS: 9.
+        0x05, 0x00, 0x02, 0x00, 0x09, 0x00, 0x00, 0x00, //
+        // nlmsghdr: length 20, NLMSG_DONE.
+        0x14, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// The reply to an `NL80211_CMD_GET_STATION` dump on a weak link, with only the 16-bit
+    /// bitrate.
+    const WEAK_STATION: &[u8] = &[
+        // nlmsghdr: length 52, family 0x22, NLM_F_MULTI, sequence 3, port ID.
+        0x34, 0x00, 0x00, 0x00, 0x22, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00, 0x00, 0x2e, 0x1f, 0x00,
+        0x00, //
+        0x13, 0x01, 0x00, 0x00, //
+        // NL80211_ATTR_IFINDEX: 3.
+        0x08, 0x00, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, //
+        // NL80211_ATTR_STA_INFO, nested.
+        0x18, 0x00, 0x15, 0x80, //
+        // NL80211_STA_INFO_SIGNAL: -90.
+        0x05, 0x00, 0x07, 0x00, 0xa6, 0x00, 0x00, 0x00, //
+        // NL80211_STA_INFO_TX_BITRATE, nested.
+        0x0c, 0x00, 0x08, 0x80, //
+        // NL80211_RATE_INFO_BITRATE: 65.
+        0x06, 0x00, 0x01, 0x00, 0x41, 0x00, 0x00, 0x00,
+    ];
+
+    fn parse_station(datagram: &[u8]) -> Station {
+        let messages = netlink::parse_messages(datagram).unwrap();
+        let (_, attrs) = messages[0].genl().unwrap();
+        Station::from_attrs(attrs).unwrap()
+    }
+
+    #[test]
+    fn parse_interface_dump() {
+        let messages = netlink::parse_messages(INTERFACES).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|m| m.seq == 1));
+        assert_eq!(
+            libc::c_int::from(messages[2].kind),
+            libc::NLMSG_DONE,
+            "the dump ends with NLMSG_DONE"
+        );
+
+        let (_, attrs) = messages[0].genl().unwrap();
+        let interface = Interface::from_attrs(attrs).unwrap();
+        assert_eq!(interface.index, 3);
+        assert_eq!(interface.name, "wlan0");
+        assert_eq!(interface.ssid.as_deref(), Some("home"));
+        assert_eq!(interface.frequency, Some(5180));
+
+        // Access points are not shown.
+        let (_, attrs) = messages[1].genl().unwrap();
+        assert!(Interface::from_attrs(attrs).is_none());
+    }
+
+    #[test]
+    fn parse_station_dump() {
+        let station = parse_station(STATION);
+        assert_eq!(station.signal, Some(-52));
+        assert_eq!(station.bitrate, Some(8667));
+        assert_eq!(station.quality(), Some(96));
+
+        let station = parse_station(WEAK_STATION);
+        assert_eq!(station.signal, Some(-90));
+        assert_eq!(station.bitrate, Some(65));
+        assert_eq!(station.quality(), Some(20));
+    }
+
+    /// Encodes an attribute.
+    fn attr(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(4 + payload.len()).unwrap();
+        let mut r = [len.to_ne_bytes(), kind.to_ne_bytes()].concat();
+        r.extend_from_slice(payload);
+        r.resize((r.len() + 3) & !3, 0);
+        r
+    }
+
+    #[test]
+    fn prefer_32_bit_bitrate() {
+        // The 16-bit bitrate saturates on fast links, so the 32-bit one wins in either order.
+        let bitrate16 = attr(NL80211_RATE_INFO_BITRATE, &u16::MAX.to_ne_bytes());
+        let bitrate32 = attr(NL80211_RATE_INFO_BITRATE32, &100_000u32.to_ne_bytes());
+        for rates in [
+            [&bitrate16[..], &bitrate32[..]].concat(),
+            [&bitrate32[..], &bitrate16[..]].concat(),
+        ] {
+            let info = attr(NL80211_STA_INFO_TX_BITRATE, &rates);
+            let attrs = attr(NL80211_ATTR_STA_INFO, &info);
+            let station = Station::from_attrs(netlink::Attrs::new(&attrs)).unwrap();
+            assert_eq!(station.bitrate, Some(100_000));
+        }
+    }
+
+    #[test]
+    fn clamp_quality() {
+        let quality = |signal| {
+            Station {
+                signal: Some(signal),
+                bitrate: None,
+            }
+            .quality()
+        };
+        assert_eq!(quality(-30), Some(100));
+        assert_eq!(quality(-50), Some(100));
+        assert_eq!(quality(-75), Some(50));
+        assert_eq!(quality(-100), Some(0));
+        assert_eq!(quality(-128), Some(0));
+    }
+}
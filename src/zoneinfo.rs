@@ -0,0 +1,511 @@
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+
+use time::{Date, Month};
+
+/// The directory of the time zone database.
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid TZif file")
+}
+
+/// A date in a POSIX TZ string.
+enum RuleDate {
+    /// `Jn`: the day of the year from 1 to 365, without February 29.
+    Julian(u16),
+    /// `n`: the day of the year from 0 to 365, with February 29.
+    Ordinal(u16),
+    /// `Mm.w.d`: the day `d` (0 is Sunday) of the week `w` of the month `m`. The week 5 is the
+    /// last one of the month.
+    Weekday { month: u8, week: u8, day: u8 },
+}
+
+impl RuleDate {
+    /// Returns the number of days between the epoch and this date in `year`.
+    fn days_since_epoch(&self, year: i32) -> Option<i64> {
+        let date = match *self {
+            RuleDate::Julian(n) => {
+                let leap_day = time::util::is_leap_year(year) && n >= 60;
+                Date::from_ordinal_date(year, n + u16::from(leap_day)).ok()?
+            }
+            RuleDate::Ordinal(n) => Date::from_ordinal_date(year, n + 1).ok()?,
+            RuleDate::Weekday { month, week, day } => {
+                let month = Month::try_from(month).ok()?;
+                let first = Date::from_calendar_date(year, month, 1).ok()?;
+                let first_day = first.weekday().number_days_from_sunday();
+                let d = 1 + (7 + day - first_day) % 7 + (week - 1) * 7;
+                // The week 5 may not exist, in which case the week 4 is the last one.
+                Date::from_calendar_date(year, month, d)
+                    .or_else(|_| Date::from_calendar_date(year, month, d - 7))
+                    .ok()?
+            }
+        };
+        // The Julian day of 1970-01-01.
+        Some(i64::from(date.to_julian_day()) - 2_440_588)
+    }
+}
+
+/// The daylight saving time of a POSIX TZ string.
+struct Dst {
+    offset: i32,
+    /// The date and the local standard time at which it starts.
+    start: (RuleDate, i32),
+    /// The date and the local daylight saving time at which it ends.
+    end: (RuleDate, i32),
+}
+
+/// The rule of a POSIX TZ string such as `CET-1CEST,M3.5.0,M10.5.0/3`. It gives the offset of
+/// the times after the last transition of a TZif file.
+struct Rule {
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+/// Parses the TZ strings found in the footer of TZif files. See RFC 8536.
+struct RuleParser<'a> {
+    s: &'a [u8],
+}
+
+impl<'a> RuleParser<'a> {
+    fn eat(&mut self, b: u8) -> bool {
+        if self.s.first() == Some(&b) {
+            self.s = &self.s[1..];
+            return true;
+        }
+        false
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        let len = self.s.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 || len > 3 {
+            return None;
+        }
+        let n = std::str::from_utf8(&self.s[..len]).ok()?.parse().ok()?;
+        self.s = &self.s[len..];
+        Some(n)
+    }
+
+    /// Parses a time zone abbreviation, such as `CET` or `<+03>`.
+    fn name(&mut self) -> Option<()> {
+        let len = if self.eat(b'<') {
+            let len = self.s.iter().position(|b| *b == b'>')?;
+            self.s = &self.s[len + 1..];
+            len
+        } else {
+            let len = self
+                .s
+                .iter()
+                .take_while(|b| b.is_ascii_alphabetic())
+                .count();
+            self.s = &self.s[len..];
+            len
+        };
+        if len < 3 {
+            return None;
+        }
+        Some(())
+    }
+
+    /// Parses `[+-]hh[:mm[:ss]]` into seconds.
+    fn time(&mut self) -> Option<i32> {
+        let sign = if self.eat(b'-') {
+            -1
+        } else {
+            self.eat(b'+');
+            1
+        };
+        let mut secs = self.number()? * 3600;
+        if self.eat(b':') {
+            secs += self.number()? * 60;
+            if self.eat(b':') {
+                secs += self.number()?;
+            }
+        }
+        Some(sign * secs)
+    }
+
+    fn date(&mut self) -> Option<RuleDate> {
+        let date = if self.eat(b'J') {
+            match u16::try_from(self.number()?).ok()? {
+                n @ 1..=365 => RuleDate::Julian(n),
+                _ => return None,
+            }
+        } else if self.eat(b'M') {
+            let month = u8::try_from(self.number()?).ok()?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let week = u8::try_from(self.number()?).ok()?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let day = u8::try_from(self.number()?).ok()?;
+            if !(1..=12).contains(&month) || !(1..=5).contains(&week) || day > 6 {
+                return None;
+            }
+            RuleDate::Weekday { month, week, day }
+        } else {
+            match u16::try_from(self.number()?).ok()? {
+                n @ 0..=365 => RuleDate::Ordinal(n),
+                _ => return None,
+            }
+        };
+        Some(date)
+    }
+
+    /// Parses a date followed by an optional time, which is 02:00 by default.
+    fn transition(&mut self) -> Option<(RuleDate, i32)> {
+        let date = self.date()?;
+        let time = if self.eat(b'/') { self.time()? } else { 7200 };
+        Some((date, time))
+    }
+
+    fn rule(&mut self) -> Option<Rule> {
+        self.name()?;
+        // POSIX offsets are positive west of Greenwich.
+        let std_offset = -self.time()?;
+        if self.s.is_empty() {
+            return Some(Rule {
+                std_offset,
+                dst: None,
+            });
+        }
+        self.name()?;
+        let offset = if self.s.first() == Some(&b',') {
+            std_offset + 3600
+        } else {
+            -self.time()?
+        };
+        if !self.eat(b',') {
+            return None;
+        }
+        let start = self.transition()?;
+        if !self.eat(b',') {
+            return None;
+        }
+        let end = self.transition()?;
+        if !self.s.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            std_offset,
+            dst: Some(Dst { offset, start, end }),
+        })
+    }
+}
+
+impl Rule {
+    fn parse(s: &[u8]) -> Option<Self> {
+        RuleParser { s }.rule()
+    }
+
+    /// Returns the offset from UTC in seconds at a Unix time.
+    fn offset_at(&self, time: i64) -> i32 {
+        let dst = match &self.dst {
+            Some(d) => d,
+            None => return self.std_offset,
+        };
+        let local = time + i64::from(self.std_offset);
+        let year = match time::OffsetDateTime::from_unix_timestamp(local) {
+            Ok(t) => t.year(),
+            Err(_) => return self.std_offset,
+        };
+        let instant = |(date, secs): &(RuleDate, i32), offset: i32| {
+            Some(date.days_since_epoch(year)? * 86400 + i64::from(*secs) - i64::from(offset))
+        };
+        let (start, end) = match (
+            instant(&dst.start, self.std_offset),
+            instant(&dst.end, dst.offset),
+        ) {
+            (Some(s), Some(e)) => (s, e),
+            _ => return self.std_offset,
+        };
+        // In the southern hemisphere, daylight saving time spans the end of the year.
+        let is_dst = if start < end {
+            start <= time && time < end
+        } else {
+            !(end <= time && time < start)
+        };
+        if is_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+/// A time zone of the database, read from a TZif file. See RFC 8536.
+pub(crate) struct TimeZone {
+    /// The Unix times of the transitions, and the offsets from UTC in seconds that start at them.
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition.
+    initial_offset: i32,
+    /// The rule for the times after the last transition.
+    rule: Option<Rule>,
+}
+
+impl TimeZone {
+    /// Reads a time zone such as `Europe/Paris` from the database.
+    pub(crate) fn load(name: &str) -> io::Result<Self> {
+        let path = Path::new(name);
+        if name.is_empty() || path.is_absolute() || path.components().any(|c| c.as_os_str() == "..")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid time zone name",
+            ));
+        }
+        TimeZone::parse(&std::fs::read(Path::new(ZONEINFO_DIR).join(path))?)
+    }
+
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let (header, data) = Header::parse(data)?;
+        if header.version < b'2' {
+            return TimeZone::parse_block(&header, data, 4, None);
+        }
+        // Skip the version 1 data block, which has 32-bit times, for the version 2 one that is
+        // followed by a footer.
+        let data = data.get(header.data_len(4)..).ok_or_else(invalid_data)?;
+        let (header, data) = Header::parse(data)?;
+        let footer = data.get(header.data_len(8)..).ok_or_else(invalid_data)?;
+        // The footer is a TZ string between two newlines. It may be empty.
+        let footer = footer.strip_prefix(b"\n").ok_or_else(invalid_data)?;
+        let end = footer
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(invalid_data)?;
+        let rule = match end {
+            0 => None,
+            _ => Some(Rule::parse(&footer[..end]).ok_or_else(invalid_data)?),
+        };
+        TimeZone::parse_block(&header, data, 8, rule)
+    }
+
+    /// Parses the transitions of a data block whose times are `time_size` bytes long.
+    fn parse_block(
+        header: &Header,
+        data: &[u8],
+        time_size: usize,
+        rule: Option<Rule>,
+    ) -> io::Result<Self> {
+        if data.len() < header.data_len(time_size) {
+            return Err(invalid_data());
+        }
+        let (times, data) = data.split_at(header.timecnt * time_size);
+        let (indices, types) = data.split_at(header.timecnt);
+        // Each local time type starts with its offset as a 32-bit integer.
+        let offset = |i: usize| {
+            let bytes = <[u8; 4]>::try_from(&types[i * 6..i * 6 + 4]).unwrap();
+            i32::from_be_bytes(bytes)
+        };
+        let mut transitions = Vec::with_capacity(header.timecnt);
+        for (time, index) in times.chunks_exact(time_size).zip(indices) {
+            let time = match time_size {
+                4 => i64::from(i32::from_be_bytes(<[u8; 4]>::try_from(time).unwrap())),
+                _ => i64::from_be_bytes(<[u8; 8]>::try_from(time).unwrap()),
+            };
+            let index = usize::from(*index);
+            if index >= header.typecnt {
+                return Err(invalid_data());
+            }
+            transitions.push((time, offset(index)));
+        }
+        Ok(Self {
+            transitions,
+            initial_offset: offset(0),
+            rule,
+        })
+    }
+
+    /// Returns the offset from UTC in seconds at a Unix time.
+    pub(crate) fn offset_at(&self, time: i64) -> i32 {
+        let i = self.transitions.partition_point(|(t, _)| *t <= time);
+        match &self.rule {
+            Some(rule) if i == self.transitions.len() => rule.offset_at(time),
+            _ if i == 0 => self.initial_offset,
+            _ => self.transitions[i - 1].1,
+        }
+    }
+}
+
+/// The header of a TZif data block, with the number of each kind of record.
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    /// Parses a header and returns it with the data that follows it.
+    fn parse(data: &[u8]) -> io::Result<(Self, &[u8])> {
+        if data.len() < 44 || &data[..4] != b"TZif" {
+            return Err(invalid_data());
+        }
+        let count = |i: usize| {
+            let bytes = <[u8; 4]>::try_from(&data[20 + i * 4..24 + i * 4]).unwrap();
+            usize::try_from(u32::from_be_bytes(bytes)).unwrap()
+        };
+        let header = Self {
+            version: data[4],
+            isutcnt: count(0),
+            isstdcnt: count(1),
+            leapcnt: count(2),
+            timecnt: count(3),
+            typecnt: count(4),
+            charcnt: count(5),
+        };
+        if header.typecnt == 0 {
+            return Err(invalid_data());
+        }
+        Ok((header, &data[44..]))
+    }
+
+    /// Returns the length of the data block that follows the header.
+    fn data_len(&self, time_size: usize) -> usize {
+        self.timecnt * time_size
+            + self.timecnt
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TZif version 2 file with the two transitions of 2023 between CET and CEST, followed by
+    /// the rule of Central Europe.
+    const TZIF: &[u8] = b"\
+        TZif2\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+        \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x02\0\0\0\x02\0\0\0\x09\
+        \x64\x1f\x99\x10\x65\x3d\xae\x90\
+        \x01\0\
+        \0\0\x0e\x10\0\0\0\0\x1c\x20\x01\x04\
+        CET\0CEST\0\
+        TZif2\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+        \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x02\0\0\0\x02\0\0\0\x09\
+        \0\0\0\0\x64\x1f\x99\x10\0\0\0\0\x65\x3d\xae\x90\
+        \x01\0\
+        \0\0\x0e\x10\0\0\0\0\x1c\x20\x01\x04\
+        CET\0CEST\0\
+        \nCET-1CEST,M3.5.0,M10.5.0/3\n";
+
+    /// 2023-03-26 01:00 UTC.
+    const SPRING_2023: i64 = 1_679_792_400;
+    /// 2023-10-29 01:00 UTC.
+    const FALL_2023: i64 = 1_698_541_200;
+
+    #[test]
+    fn central_europe() {
+        let rule = Rule::parse(b"CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2024-03-31 at 02:00 CET, the last Sunday of March.
+        let spring = 1_711_846_800;
+        assert_eq!(rule.offset_at(spring - 1), 3600);
+        assert_eq!(rule.offset_at(spring), 7200);
+        // 2024-10-27 at 03:00 CEST, the last Sunday of October.
+        let fall = 1_729_990_800;
+        assert_eq!(rule.offset_at(fall - 1), 7200);
+        assert_eq!(rule.offset_at(fall), 3600);
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        let rule = Rule::parse(b"AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        // 2024-01-01 and 2024-12-31 are in the summer.
+        assert_eq!(rule.offset_at(1_704_067_200), 11 * 3600);
+        assert_eq!(rule.offset_at(1_735_603_200), 11 * 3600);
+        // 2024-04-07 at 03:00 AEDT, the first Sunday of April.
+        let fall = 1_712_419_200;
+        assert_eq!(rule.offset_at(fall - 1), 11 * 3600);
+        assert_eq!(rule.offset_at(fall), 10 * 3600);
+        // 2024-10-06 at 02:00 AEST, the first Sunday of October.
+        let spring = 1_728_144_000;
+        assert_eq!(rule.offset_at(spring - 1), 10 * 3600);
+        assert_eq!(rule.offset_at(spring), 11 * 3600);
+    }
+
+    #[test]
+    fn julian_day_in_leap_year() {
+        // J60 is March 1 whether or not the year has a February 29.
+        let rule = Rule::parse(b"STD0DST,J60/0,J300/0").unwrap();
+        let march_1_2024 = 1_709_251_200;
+        assert_eq!(rule.offset_at(march_1_2024 - 1), 0);
+        assert_eq!(rule.offset_at(march_1_2024), 3600);
+        let march_1_2023 = 1_677_628_800;
+        assert_eq!(rule.offset_at(march_1_2023 - 1), 0);
+        assert_eq!(rule.offset_at(march_1_2023), 3600);
+        // Without `J`, the days count from 0 and include February 29.
+        let rule = Rule::parse(b"STD0DST,59/0,300/0").unwrap();
+        let february_29_2024 = 1_709_164_800;
+        assert_eq!(rule.offset_at(february_29_2024 - 1), 0);
+        assert_eq!(rule.offset_at(february_29_2024), 3600);
+        // 2024-10-27 at 00:00 DST.
+        let october_27_2024 = 1_729_983_600;
+        assert_eq!(rule.offset_at(october_27_2024 - 1), 3600);
+        assert_eq!(rule.offset_at(october_27_2024), 0);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule = Rule::parse(b"<+03>-3").unwrap();
+        assert_eq!(rule.std_offset, 3 * 3600);
+        assert!(rule.dst.is_none());
+        let rule = Rule::parse(b"EST5EDT4:30,M3.2.0/-1,M11.1.0/25:30:15").unwrap();
+        let dst = rule.dst.unwrap();
+        assert_eq!(
+            (rule.std_offset, dst.offset),
+            (-5 * 3600, -(4 * 3600 + 1800))
+        );
+        assert_eq!(dst.start.1, -3600);
+        assert_eq!(dst.end.1, 25 * 3600 + 30 * 60 + 15);
+        for s in [
+            "CET",
+            "CE-1",
+            "CET-1CEST",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.1.0,M10.5.0",
+            "CET-1CEST,J0,J10",
+            "CET-1CEST,M3.5.0,M10.5.0/3x",
+        ] {
+            assert!(Rule::parse(s.as_bytes()).is_none(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_tzif() {
+        let zone = TimeZone::parse(TZIF).unwrap();
+        assert_eq!(zone.transitions, [(SPRING_2023, 7200), (FALL_2023, 3600)]);
+        assert_eq!(zone.offset_at(SPRING_2023 - 1), 3600);
+        assert_eq!(zone.offset_at(SPRING_2023), 7200);
+        assert_eq!(zone.offset_at(FALL_2023 - 1), 7200);
+        // The rule takes over after the last transition.
+        assert_eq!(zone.offset_at(FALL_2023), 3600);
+        assert_eq!(zone.offset_at(1_719_792_000), 7200);
+    }
+
+    #[test]
+    fn parse_tzif_version_1() {
+        // Only the first data block is read, and there is no rule.
+        let mut data = TZIF.to_vec();
+        data[4] = 0;
+        let zone = TimeZone::parse(&data).unwrap();
+        assert_eq!(zone.transitions, [(SPRING_2023, 7200), (FALL_2023, 3600)]);
+        assert_eq!(zone.offset_at(1_719_792_000), 3600);
+    }
+
+    #[test]
+    fn reject_truncated_tzif() {
+        for len in [0, 43, 44, 80, TZIF.len() - 1] {
+            assert!(TimeZone::parse(&TZIF[..len]).is_err(), "{}", len);
+        }
+    }
+}